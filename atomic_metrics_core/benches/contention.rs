@@ -0,0 +1,62 @@
+//! Compare a single `AtomicU64` with a `ShardedCounter` under multi-threaded increments.
+//!
+//! Run with `cargo bench -p atomic_metrics_core --bench contention`.
+
+use atomic_metrics_core::ShardedCounter;
+use std::{
+    hint::black_box,
+    sync::atomic::{AtomicU64, Ordering},
+    thread,
+    time::{Duration, Instant},
+};
+
+const INCREMENTS_PER_THREAD: u64 = 2_000_000;
+
+static PLAIN: AtomicU64 = AtomicU64::new(0);
+static SHARDED: ShardedCounter = ShardedCounter::new(0);
+
+/// Let `threads` threads each call `increment` and return the elapsed time.
+fn run(threads: usize, increment: fn()) -> Duration {
+    let start = Instant::now();
+    thread::scope(|scope| {
+        for _ in 0..threads {
+            scope.spawn(|| {
+                for _ in 0..INCREMENTS_PER_THREAD {
+                    increment();
+                }
+            });
+        }
+    });
+    start.elapsed()
+}
+
+fn ns_per_increment(elapsed: Duration, threads: usize) -> f64 {
+    elapsed.as_nanos() as f64 / (threads as u64 * INCREMENTS_PER_THREAD) as f64
+}
+
+fn main() {
+    let max_threads = thread::available_parallelism().map_or(4, |n| n.get());
+
+    println!("threads  AtomicU64 [ns/op]  ShardedCounter [ns/op]");
+    let mut threads = 1;
+    while threads <= max_threads {
+        let plain = run(threads, || {
+            black_box(PLAIN.fetch_add(1, Ordering::Relaxed));
+        });
+        let sharded = run(threads, || {
+            black_box(SHARDED.fetch_add(1, Ordering::Relaxed));
+        });
+
+        println!(
+            "{threads:>7}  {:>17.2}  {:>22.2}",
+            ns_per_increment(plain, threads),
+            ns_per_increment(sharded, threads)
+        );
+        threads *= 2;
+    }
+
+    assert_eq!(
+        PLAIN.load(Ordering::Relaxed),
+        SHARDED.load(Ordering::Relaxed)
+    );
+}
@@ -0,0 +1,315 @@
+//! Configurable generation of the `MetricsRecorder` in build scripts.
+
+use crate::{
+    discover::{get_metrics, Sources},
+    generate::{snake_case, write_metrics, Options},
+    indent::Indented,
+    manifest::load_manifest,
+    workspace::SourceCrate,
+    Metric,
+};
+use anyhow::{bail, Result};
+use std::{
+    env, fs,
+    io::{self, Write},
+    path::{Path, PathBuf},
+};
+
+/// Atomic type backing the plain counters of the generated `MetricsRecorder`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AtomicType {
+    #[default]
+    U64,
+    /// Halves the memory of plain counters, which wrap around at `u32::MAX`. The generated code
+    /// still requires 64-bit atomics for gauges, histograms and the other counters.
+    U32,
+    Usize,
+}
+
+impl AtomicType {
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            AtomicType::U64 => "AtomicU64",
+            AtomicType::U32 => "AtomicU32",
+            AtomicType::Usize => "AtomicUsize",
+        }
+    }
+}
+
+/// Builder for generating the `MetricsRecorder` from a build script.
+///
+/// ```no_run
+/// atomic_metrics_core::MetricsBuilder::new()
+///     .include("src/**/*.rs")
+///     .exclude("src/bin/**")
+///     .output("recorder.rs")
+///     .type_name("Metrics")
+///     .static_name("METRICS")
+///     .visibility("pub(crate)")
+///     .generate()
+///     .unwrap();
+/// ```
+#[derive(Debug, Clone)]
+pub struct MetricsBuilder {
+    include: Vec<String>,
+    exclude: Vec<String>,
+    members: Vec<String>,
+    crate_dirs: Vec<PathBuf>,
+    output: PathBuf,
+    pub(crate) type_name: String,
+    pub(crate) static_name: String,
+    implicit_recorder: bool,
+    pub(crate) visibility: String,
+    pub(crate) atomic_type: AtomicType,
+    format: bool,
+    pub(crate) options: Options,
+    metrics: Option<Vec<Metric>>,
+    /// Path of the `metrics.toml` manifest and whether it is checked strictly.
+    manifest: Option<(PathBuf, bool)>,
+}
+
+impl Default for MetricsBuilder {
+    fn default() -> Self {
+        Self {
+            include: Vec::new(),
+            exclude: Vec::new(),
+            members: Vec::new(),
+            crate_dirs: Vec::new(),
+            output: PathBuf::from("metrics.rs"),
+            type_name: "MetricsRecorder".to_owned(),
+            static_name: "METRICS_RECORDER".to_owned(),
+            implicit_recorder: true,
+            visibility: "pub".to_owned(),
+            atomic_type: AtomicType::default(),
+            format: true,
+            options: Options::default(),
+            metrics: None,
+            manifest: None,
+        }
+    }
+}
+
+impl MetricsBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Discover metrics in the files matching the glob `pattern`.
+    ///
+    /// Without any included pattern, `src/**/*.rs` is scanned.
+    pub fn include(mut self, pattern: &str) -> Self {
+        self.include.push(pattern.to_owned());
+        self
+    }
+
+    /// Skip the files matching the glob `pattern` during discovery.
+    pub fn exclude(mut self, pattern: &str) -> Self {
+        self.exclude.push(pattern.to_owned());
+        self
+    }
+
+    /// Also discover metrics in the sources of the workspace member `name`.
+    ///
+    /// The member is looked up in the `Cargo.toml` of the workspace containing the crate being
+    /// built. All its files matching `src/**/*.rs` are scanned, and `#[cfg]` attributes are
+    /// evaluated with the configuration of the crate being built.
+    pub fn workspace_member(mut self, name: &str) -> Self {
+        self.members.push(name.to_owned());
+        self
+    }
+
+    /// Also discover metrics in the sources of the crate in the directory `dir`.
+    ///
+    /// All files of the crate matching `src/**/*.rs` are scanned.
+    pub fn crate_dir(mut self, dir: impl AsRef<Path>) -> Self {
+        self.crate_dirs.push(dir.as_ref().to_owned());
+        self
+    }
+
+    /// Use `metrics` instead of discovering them in the source files.
+    ///
+    /// Counters used with `tick_metric_with_exemplar!` then need to be declared with
+    /// [`Options::exemplars`].
+    pub fn metrics(mut self, metrics: &[Metric]) -> Self {
+        self.metrics = Some(metrics.to_vec());
+        self
+    }
+
+    /// Generate the metrics declared in the `metrics.toml` manifest at `path`.
+    ///
+    /// The discovered usages of the recorder are checked against the manifest, see
+    /// [`generate_metrics_recorder_from_manifest`](crate::generate_metrics_recorder_from_manifest)
+    /// for `strict`. Label enums and options of the manifest are added to those of
+    /// [`MetricsBuilder::options`].
+    pub fn manifest(mut self, path: impl AsRef<Path>, strict: bool) -> Self {
+        self.manifest = Some((path.as_ref().to_owned(), strict));
+        self
+    }
+
+    /// Write the generated code to `path`, relative to `OUT_DIR`, instead of `metrics.rs`.
+    pub fn output(mut self, path: impl AsRef<Path>) -> Self {
+        self.output = path.as_ref().to_owned();
+        self
+    }
+
+    /// Name the generated type `name` instead of `MetricsRecorder`.
+    ///
+    /// The other generated items are named accordingly, e.g. `MetricsSnapshot`, `MetricId` and
+    /// `DYNAMIC_METRICS` for `MetricsRecorder` and `AppMetricsSnapshot`, `AppMetricId` and
+    /// `APP_DYNAMIC_METRICS` for `AppMetrics`, so several recorders can live in one module.
+    pub fn type_name(mut self, name: &str) -> Self {
+        self.type_name = name.to_owned();
+        self
+    }
+
+    /// Name the generated static `name` instead of `METRICS_RECORDER`.
+    ///
+    /// Only macro usages with a recorder path ending in `name`, e.g.
+    /// `tick_metric!(in crate::metrics::NAME, requests)`, and usages without a recorder path are
+    /// metrics of this recorder, see [`MetricsBuilder::implicit_recorder`].
+    pub fn static_name(mut self, name: &str) -> Self {
+        self.static_name = name.to_owned();
+        self
+    }
+
+    /// Whether macro usages without a recorder path, which refer to `METRICS_RECORDER` in scope,
+    /// are metrics of this recorder. Enabled by default.
+    ///
+    /// Disable this for all but one recorder when keeping several recorders in one crate.
+    pub fn implicit_recorder(mut self, implicit: bool) -> Self {
+        self.implicit_recorder = implicit;
+        self
+    }
+
+    /// Declare the generated items with `visibility`, e.g. `pub(crate)`, instead of `pub`.
+    pub fn visibility(mut self, visibility: &str) -> Self {
+        self.visibility = visibility.to_owned();
+        self
+    }
+
+    /// Back plain counters by `atomic_type` instead of `AtomicU64`.
+    ///
+    /// Hot, labeled and dynamically labeled counters always use 64-bit atomics.
+    pub fn atomic_type(mut self, atomic_type: AtomicType) -> Self {
+        self.atomic_type = atomic_type;
+        self
+    }
+
+    /// Whether to indent the generated code, enabled by default.
+    ///
+    /// The code is indented while it is written, without running `rustfmt`.
+    pub fn format(mut self, format: bool) -> Self {
+        self.format = format;
+        self
+    }
+
+    /// Customize the generated metrics with `options`.
+    pub fn options(mut self, options: Options) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Name of the generated snapshot type.
+    pub(crate) fn snapshot_name(&self) -> String {
+        format!("{}Snapshot", self.base_name())
+    }
+
+    /// Name of the generated enum identifying the metrics, e.g. `MetricId` for
+    /// `MetricsRecorder` and `SubsystemMetricId` for `SubsystemRecorder`.
+    pub(crate) fn id_name(&self) -> String {
+        let base = self.base_name();
+        format!("{}MetricId", base.strip_suffix("Metrics").unwrap_or(base))
+    }
+
+    /// Name of the generated registry of runtime metrics, e.g. `DYNAMIC_METRICS` for
+    /// `MetricsRecorder` and `SUBSYSTEM_DYNAMIC_METRICS` for `SubsystemRecorder`.
+    pub(crate) fn registry_name(&self) -> String {
+        let base = self.base_name();
+        match snake_case(base.strip_suffix("Metrics").unwrap_or(base)) {
+            prefix if prefix.is_empty() => "DYNAMIC_METRICS".to_owned(),
+            prefix => format!("{}_DYNAMIC_METRICS", prefix.to_uppercase()),
+        }
+    }
+
+    /// Type name without the `Recorder` suffix.
+    fn base_name(&self) -> &str {
+        self.type_name
+            .strip_suffix("Recorder")
+            .unwrap_or(&self.type_name)
+    }
+
+    /// Discover the metrics if none were given and write the generated code.
+    pub fn generate(&self) -> Result<()> {
+        for (what, name) in [("type", &self.type_name), ("static", &self.static_name)] {
+            if syn::parse_str::<syn::Ident>(name).is_err() {
+                bail!("{what} name `{name}` is not a valid identifier");
+            }
+        }
+        if syn::parse_str::<syn::Visibility>(&self.visibility).is_err() {
+            bail!("`{}` is not a valid visibility", self.visibility);
+        }
+
+        let (metrics, options) = match (&self.metrics, &self.manifest) {
+            (Some(_), Some(_)) => bail!("metrics can either be given or declared in a manifest"),
+            (Some(metrics), None) => (metrics.clone(), self.options.clone()),
+            (None, Some((path, strict))) => load_manifest(
+                path,
+                *strict,
+                &self.sources()?,
+                &self.static_name,
+                self.implicit_recorder,
+                self.options.clone(),
+            )?,
+            (None, None) => get_metrics(
+                &self.sources()?,
+                &self.static_name,
+                self.implicit_recorder,
+                self.options.clone(),
+            )?,
+        };
+        let builder = Self {
+            options,
+            ..self.clone()
+        };
+
+        // Compiling the metrics out leaves an empty `MetricsRecorder` with the same API.
+        let disabled = env::var_os("CARGO_CFG_ATOMIC_METRICS_DISABLED").is_some();
+
+        let output = Path::new(&env::var("OUT_DIR")?).join(&self.output);
+        let out = io::BufWriter::new(fs::File::create(output)?);
+        if self.format {
+            let mut out = Indented::new(out);
+            write_metrics(&mut out, &builder, &metrics, disabled)?;
+            out.flush()?;
+        } else {
+            let mut out = out;
+            write_metrics(&mut out, &builder, &metrics, disabled)?;
+            out.flush()?;
+        }
+
+        Ok(())
+    }
+
+    /// Find the source files to discover metrics in and have cargo rerun the build script when
+    /// they change.
+    fn sources(&self) -> Result<Sources> {
+        let mut include = if self.include.is_empty() {
+            vec!["src/**/*.rs".to_owned()]
+        } else {
+            self.include.clone()
+        };
+        let mut crates = Vec::new();
+        for name in self.members.iter() {
+            crates.push(SourceCrate::member(name)?);
+        }
+        for dir in self.crate_dirs.iter() {
+            crates.push(SourceCrate::at(dir)?);
+        }
+        include.extend(crates.iter().map(SourceCrate::pattern));
+
+        let mut sources = Sources::find(&include, &self.exclude)?;
+        sources.crates = crates;
+        sources.rerun_if_changed();
+        Ok(sources)
+    }
+}
@@ -0,0 +1,735 @@
+//! Discovery of metrics by parsing the source files for macro usages.
+
+use crate::{workspace::SourceCrate, Metric, MetricKind, Options};
+use anyhow::{bail, Result};
+use glob::{glob, Pattern};
+use proc_macro2::{Delimiter, TokenStream, TokenTree};
+use std::{
+    collections::{hash_map::Entry, BTreeSet, HashMap, HashSet},
+    env, fs,
+    path::{Path, PathBuf},
+};
+use syn::{
+    punctuated::Punctuated,
+    visit::{self, Visit},
+    Attribute, Expr, ImplItem, Item, Lit, Meta, Stmt, Token, TraitItem,
+};
+
+/// Metric macros and the kind of metric they operate on.
+const METRIC_MACROS: &[(&str, MetricKind)] = &[
+    ("get_counter", MetricKind::Counter),
+    ("increment_metric", MetricKind::Counter),
+    ("tick_metric", MetricKind::Counter),
+    ("tick_metric_with_exemplar", MetricKind::Counter),
+    ("set_metric", MetricKind::Counter),
+    ("reset_metric", MetricKind::Counter),
+    ("load_metric", MetricKind::Counter),
+    ("inc_gauge", MetricKind::Gauge),
+    ("dec_gauge", MetricKind::Gauge),
+    ("set_gauge", MetricKind::Gauge),
+    ("load_gauge", MetricKind::Gauge),
+    ("observe_histogram", MetricKind::Histogram),
+];
+
+/// Usage of a metric macro in a source file.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct MetricUsage {
+    pub name: String,
+    pub kind: MetricKind,
+    pub file: PathBuf,
+    /// One-based line of the metric name in `file`.
+    pub line: usize,
+    /// Path of the recorder given after `in`, `None` for the default `METRICS_RECORDER`.
+    pub recorder: Option<String>,
+    /// Name of the macro, e.g. `tick_metric`.
+    pub macro_name: &'static str,
+}
+
+impl MetricUsage {
+    /// Whether this usage refers to the recorder named `static_name`.
+    ///
+    /// Usages with an `in` path refer to the recorder named like the last segment of the path.
+    /// Usages without one refer to the recorder only if it is `implicit`.
+    pub(crate) fn uses_recorder(&self, static_name: &str, implicit: bool) -> bool {
+        match &self.recorder {
+            Some(path) => path.rsplit("::").next() == Some(static_name),
+            None => implicit,
+        }
+    }
+}
+
+/// Find all metric macro usages in the files matching the glob `pattern`.
+///
+/// Usages inside comments, string literals and code disabled by `#[cfg]` are ignored. Usages are
+/// ordered by file and position within the file.
+pub fn find_metric_usages(pattern: &str) -> Result<Vec<MetricUsage>> {
+    find_usages(&Sources::find(&[pattern.to_owned()], &[])?)
+}
+
+/// Source files and the directories containing them, as covered by glob patterns.
+#[derive(Debug, Default)]
+pub(crate) struct Sources {
+    pub(crate) files: BTreeSet<PathBuf>,
+    /// Directories in which new source files would match the patterns, including the roots of
+    /// the patterns even if they do not exist yet.
+    pub(crate) dirs: BTreeSet<PathBuf>,
+    /// Other crates whose files are included, to name them in diagnostics.
+    pub(crate) crates: Vec<SourceCrate>,
+}
+
+impl Sources {
+    /// Find the files matching any of the `include` glob patterns but none of the `exclude` glob
+    /// patterns.
+    pub(crate) fn find(include: &[String], exclude: &[String]) -> Result<Self> {
+        let exclude = exclude
+            .iter()
+            .map(|pattern| Pattern::new(pattern))
+            .collect::<Result<Vec<_>, _>>()?;
+        let mut sources = Sources::default();
+
+        for pattern in include {
+            sources.files.extend(
+                glob(pattern)?
+                    .filter_map(|x| x.ok())
+                    .filter(|path| path.is_file()),
+            );
+
+            sources.dirs.insert(glob_root(pattern));
+            if let Some(dir_pattern) = Path::new(pattern).parent() {
+                let dir_pattern = dir_pattern.to_string_lossy();
+                if !dir_pattern.is_empty() {
+                    sources.dirs.extend(
+                        glob(&dir_pattern)?
+                            .filter_map(|x| x.ok())
+                            .filter(|path| path.is_dir()),
+                    );
+                }
+            }
+        }
+        sources
+            .files
+            .retain(|file| !exclude.iter().any(|pattern| pattern.matches_path(file)));
+
+        Ok(sources)
+    }
+
+    /// Describe the location of `line` in `file`, naming the crate of the file.
+    fn location(&self, file: &Path, line: usize) -> String {
+        let krate = self
+            .crates
+            .iter()
+            .find(|krate| file.starts_with(&krate.dir))
+            .map(|krate| krate.name.clone())
+            .or_else(|| env::var("CARGO_PKG_NAME").ok());
+
+        match krate {
+            Some(krate) => format!("{}:{line} (crate `{krate}`)", file.display()),
+            None => format!("{}:{line}", file.display()),
+        }
+    }
+
+    /// Tell cargo to rerun the build script when any of the files changes or a file is added to or
+    /// removed from any of the directories.
+    pub(crate) fn rerun_if_changed(&self) {
+        for path in self.dirs.iter().chain(self.files.iter()) {
+            println!("cargo:rerun-if-changed={}", path.display());
+        }
+    }
+}
+
+/// Directory part of a glob `pattern` before the first component containing wildcards.
+fn glob_root(pattern: &str) -> PathBuf {
+    let root: Vec<_> = Path::new(pattern)
+        .parent()
+        .into_iter()
+        .flat_map(|parent| parent.components())
+        .take_while(|component| {
+            !component
+                .as_os_str()
+                .to_string_lossy()
+                .contains(['*', '?', '['])
+        })
+        .collect();
+
+    if root.is_empty() {
+        PathBuf::from(".")
+    } else {
+        root.iter().collect()
+    }
+}
+
+/// Find all metric macro usages in the `sources`.
+pub(crate) fn find_usages(sources: &Sources) -> Result<Vec<MetricUsage>> {
+    let cfg = Cfg::from_env();
+    let mut usages = Vec::new();
+
+    for src_file in sources.files.iter() {
+        if let Ok(contents) = fs::read_to_string(src_file) {
+            let mut finder = UsageFinder {
+                cfg: &cfg,
+                file: src_file,
+                usages: &mut usages,
+            };
+            finder.find_in_source(&contents);
+        }
+    }
+
+    Ok(usages)
+}
+
+/// Extract the metrics of the recorder `static_name` by parsing the `sources` for macro usages,
+/// see [`MetricUsage::uses_recorder`], and add the options implied by the usages to `options`.
+///
+/// Fails if the same name is used with macros of different metric kinds.
+pub(crate) fn get_metrics(
+    sources: &Sources,
+    static_name: &str,
+    implicit: bool,
+    options: Options,
+) -> Result<(Vec<Metric>, Options)> {
+    let mut metrics: HashMap<String, MetricUsage> = HashMap::new();
+
+    let usages: Vec<_> = find_usages(sources)?
+        .into_iter()
+        .filter(|usage| usage.uses_recorder(static_name, implicit))
+        .collect();
+    let options = usage_options(&usages, options);
+    for usage in usages {
+        match metrics.entry(usage.name.clone()) {
+            Entry::Occupied(other) if other.get().kind != usage.kind => {
+                let other = other.get();
+                bail!(
+                    "metric `{}` is used as a {} in {} and as a {} in {}",
+                    usage.name,
+                    other.kind,
+                    sources.location(&other.file, other.line),
+                    usage.kind,
+                    sources.location(&usage.file, usage.line)
+                );
+            }
+            Entry::Occupied(_) => {}
+            Entry::Vacant(entry) => {
+                entry.insert(usage);
+            }
+        }
+    }
+
+    let mut metrics: Vec<_> = metrics
+        .into_values()
+        .map(|usage| Metric::new(usage.name, usage.kind))
+        .collect();
+    metrics.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok((metrics, options))
+}
+
+/// Add the options implied by the `usages` to `options`, i.e. exemplars for the counters used
+/// with `tick_metric_with_exemplar!` and counters only ever updated with `set_metric!` not being
+/// monotonic.
+///
+/// Loads do not update a counter. A counter which is also ticked, incremented, reset or borrowed
+/// with `get_counter!` stays monotonic, since a reset does not make a cumulative value decrease
+/// otherwise.
+pub(crate) fn usage_options(usages: &[MetricUsage], mut options: Options) -> Options {
+    let mut set_only: HashMap<&str, bool> = HashMap::new();
+    for usage in usages {
+        match usage.macro_name {
+            "tick_metric_with_exemplar" => options = options.exemplars(&usage.name),
+            "set_metric" => {
+                set_only.entry(&usage.name).or_insert(true);
+                continue;
+            }
+            _ => {}
+        }
+        if usage.kind == MetricKind::Counter && usage.macro_name != "load_metric" {
+            set_only.insert(&usage.name, false);
+        }
+    }
+
+    for (name, set_only) in set_only {
+        if set_only {
+            options = options.non_monotonic(name);
+        }
+    }
+    options
+}
+
+struct UsageFinder<'a> {
+    cfg: &'a Cfg,
+    file: &'a Path,
+    usages: &'a mut Vec<MetricUsage>,
+}
+
+impl UsageFinder<'_> {
+    fn find_in_source(&mut self, contents: &str) {
+        match syn::parse_file(contents) {
+            Ok(file) => {
+                if self.cfg.is_enabled(&file.attrs) {
+                    self.visit_file(&file);
+                }
+            }
+            // Fall back to the plain token trees for sources `syn` cannot parse. This only loses
+            // the evaluation of `#[cfg]` attributes.
+            Err(_) => match contents.parse::<TokenStream>() {
+                Ok(tokens) => self.find_in_tokens(tokens),
+                Err(err) => println!(
+                    "cargo:warning=failed to tokenize {}: {err}",
+                    self.file.display()
+                ),
+            },
+        }
+    }
+
+    /// Find macro invocations `name!(...)` in a token stream which is not parsed any further, e.g.
+    /// the arguments of another macro.
+    fn find_in_tokens(&mut self, tokens: TokenStream) {
+        let tokens: Vec<_> = tokens.into_iter().collect();
+
+        for (idx, token) in tokens.iter().enumerate() {
+            match token {
+                TokenTree::Ident(ident) => {
+                    if let (Some(TokenTree::Punct(bang)), Some(TokenTree::Group(args))) =
+                        (tokens.get(idx + 1), tokens.get(idx + 2))
+                    {
+                        if bang.as_char() == '!' && args.delimiter() != Delimiter::None {
+                            self.record(&ident.to_string(), args.stream());
+                        }
+                    }
+                }
+                TokenTree::Group(group) => self.find_in_tokens(group.stream()),
+                _ => {}
+            }
+        }
+    }
+
+    /// Record a usage if `macro_name` is a metric macro and `args` start with the metric name,
+    /// optionally preceded by `in` and the path of a recorder.
+    fn record(&mut self, macro_name: &str, args: TokenStream) {
+        let Some(&(macro_name, kind)) = METRIC_MACROS.iter().find(|(name, _)| *name == macro_name)
+        else {
+            return;
+        };
+
+        let mut args = args.into_iter().peekable();
+        let recorder = match args.peek() {
+            Some(TokenTree::Ident(keyword)) if keyword == "in" => {
+                args.next();
+                let path: String = args
+                    .by_ref()
+                    .take_while(
+                        |token| !matches!(token, TokenTree::Punct(comma) if comma.as_char() == ','),
+                    )
+                    .map(|token| token.to_string())
+                    .collect();
+                Some(path)
+            }
+            _ => None,
+        };
+
+        if let Some(TokenTree::Ident(name)) = args.next() {
+            self.usages.push(MetricUsage {
+                name: name.to_string(),
+                kind,
+                file: self.file.to_owned(),
+                line: name.span().start().line,
+                recorder,
+                macro_name,
+            });
+        }
+    }
+}
+
+impl<'ast> Visit<'ast> for UsageFinder<'_> {
+    fn visit_macro(&mut self, mac: &'ast syn::Macro) {
+        if let Some(segment) = mac.path.segments.last() {
+            self.record(&segment.ident.to_string(), mac.tokens.clone());
+        }
+        self.find_in_tokens(mac.tokens.clone());
+    }
+
+    fn visit_item(&mut self, item: &'ast Item) {
+        if self.cfg.is_enabled(item_attrs(item)) {
+            visit::visit_item(self, item);
+        }
+    }
+
+    fn visit_impl_item(&mut self, item: &'ast ImplItem) {
+        let attrs = match item {
+            ImplItem::Const(item) => &item.attrs,
+            ImplItem::Fn(item) => &item.attrs,
+            ImplItem::Type(item) => &item.attrs,
+            ImplItem::Macro(item) => &item.attrs,
+            _ => &Vec::new(),
+        };
+        if self.cfg.is_enabled(attrs) {
+            visit::visit_impl_item(self, item);
+        }
+    }
+
+    fn visit_trait_item(&mut self, item: &'ast TraitItem) {
+        let attrs = match item {
+            TraitItem::Const(item) => &item.attrs,
+            TraitItem::Fn(item) => &item.attrs,
+            TraitItem::Type(item) => &item.attrs,
+            TraitItem::Macro(item) => &item.attrs,
+            _ => &Vec::new(),
+        };
+        if self.cfg.is_enabled(attrs) {
+            visit::visit_trait_item(self, item);
+        }
+    }
+
+    fn visit_stmt(&mut self, stmt: &'ast Stmt) {
+        let enabled = match stmt {
+            Stmt::Local(local) => self.cfg.is_enabled(&local.attrs),
+            Stmt::Macro(mac) => self.cfg.is_enabled(&mac.attrs),
+            // Items and expressions are checked when visiting them.
+            Stmt::Item(_) | Stmt::Expr(..) => true,
+        };
+        if enabled {
+            visit::visit_stmt(self, stmt);
+        }
+    }
+
+    fn visit_expr(&mut self, expr: &'ast Expr) {
+        if self.cfg.is_enabled(expr_attrs(expr)) {
+            visit::visit_expr(self, expr);
+        }
+    }
+
+    fn visit_arm(&mut self, arm: &'ast syn::Arm) {
+        if self.cfg.is_enabled(&arm.attrs) {
+            visit::visit_arm(self, arm);
+        }
+    }
+
+    fn visit_field_value(&mut self, field: &'ast syn::FieldValue) {
+        if self.cfg.is_enabled(&field.attrs) {
+            visit::visit_field_value(self, field);
+        }
+    }
+}
+
+fn item_attrs(item: &Item) -> &[Attribute] {
+    match item {
+        Item::Const(item) => &item.attrs,
+        Item::Enum(item) => &item.attrs,
+        Item::ExternCrate(item) => &item.attrs,
+        Item::Fn(item) => &item.attrs,
+        Item::ForeignMod(item) => &item.attrs,
+        Item::Impl(item) => &item.attrs,
+        Item::Macro(item) => &item.attrs,
+        Item::Mod(item) => &item.attrs,
+        Item::Static(item) => &item.attrs,
+        Item::Struct(item) => &item.attrs,
+        Item::Trait(item) => &item.attrs,
+        Item::TraitAlias(item) => &item.attrs,
+        Item::Type(item) => &item.attrs,
+        Item::Union(item) => &item.attrs,
+        Item::Use(item) => &item.attrs,
+        _ => &[],
+    }
+}
+
+/// Attributes of the expressions which commonly carry a `#[cfg]`.
+fn expr_attrs(expr: &Expr) -> &[Attribute] {
+    match expr {
+        Expr::Array(expr) => &expr.attrs,
+        Expr::Assign(expr) => &expr.attrs,
+        Expr::Async(expr) => &expr.attrs,
+        Expr::Block(expr) => &expr.attrs,
+        Expr::Call(expr) => &expr.attrs,
+        Expr::Closure(expr) => &expr.attrs,
+        Expr::ForLoop(expr) => &expr.attrs,
+        Expr::If(expr) => &expr.attrs,
+        Expr::Loop(expr) => &expr.attrs,
+        Expr::Macro(expr) => &expr.attrs,
+        Expr::Match(expr) => &expr.attrs,
+        Expr::MethodCall(expr) => &expr.attrs,
+        Expr::Struct(expr) => &expr.attrs,
+        Expr::Tuple(expr) => &expr.attrs,
+        Expr::Unsafe(expr) => &expr.attrs,
+        Expr::While(expr) => &expr.attrs,
+        _ => &[],
+    }
+}
+
+/// Configuration options of the crate being built, read from the environment of the build script.
+///
+/// Outside of a build script, all configuration predicates are considered enabled.
+struct Cfg {
+    in_build_script: bool,
+    options: HashMap<String, Vec<String>>,
+    features: HashSet<String>,
+}
+
+impl Cfg {
+    fn from_env() -> Self {
+        let mut options = HashMap::new();
+        let mut features = HashSet::new();
+
+        for (key, value) in env::vars() {
+            if let Some(option) = key.strip_prefix("CARGO_CFG_") {
+                let values = value.split(',').map(|v| v.to_owned()).collect();
+                options.insert(option.to_lowercase(), values);
+            } else if let Some(feature) = key.strip_prefix("CARGO_FEATURE_") {
+                features.insert(feature.to_owned());
+            }
+        }
+
+        Self {
+            in_build_script: env::var_os("OUT_DIR").is_some(),
+            options,
+            features,
+        }
+    }
+
+    /// Check if all `#[cfg]` attributes in `attrs` may be satisfied.
+    ///
+    /// Code is only left out if one of the predicates is certainly false. Predicates which cannot
+    /// be decided in the build script, like `test`, keep the code, so metrics used on either side
+    /// of a `#[cfg(test)]` are part of the recorder.
+    fn is_enabled(&self, attrs: &[Attribute]) -> bool {
+        attrs
+            .iter()
+            .filter(|attr| attr.path().is_ident("cfg"))
+            .all(|attr| match attr.parse_args::<Meta>() {
+                Ok(predicate) => self.eval(&predicate) != Some(false),
+                // Keep code with predicates we do not understand.
+                Err(_) => true,
+            })
+    }
+
+    /// Evaluate `predicate`, `None` if it cannot be decided.
+    fn eval(&self, predicate: &Meta) -> Option<bool> {
+        if !self.in_build_script {
+            return None;
+        }
+
+        match predicate {
+            Meta::Path(path) => match path.get_ident()?.to_string().as_str() {
+                // The generated code is shared between test, doc and regular builds.
+                "test" | "doc" | "doctest" => None,
+                option => Some(self.options.contains_key(option)),
+            },
+            Meta::NameValue(name_value) => {
+                let Expr::Lit(syn::ExprLit {
+                    lit: Lit::Str(value),
+                    ..
+                }) = &name_value.value
+                else {
+                    return None;
+                };
+                let value = value.value();
+
+                match name_value.path.get_ident()?.to_string().as_str() {
+                    "feature" => Some(
+                        self.features
+                            .contains(&value.to_uppercase().replace('-', "_")),
+                    ),
+                    name => Some(
+                        self.options
+                            .get(name)
+                            .is_some_and(|values| values.contains(&value)),
+                    ),
+                }
+            }
+            Meta::List(list) => {
+                let predicates = list
+                    .parse_args_with(Punctuated::<Meta, Token![,]>::parse_terminated)
+                    .ok()?;
+                let results: Vec<_> = predicates
+                    .iter()
+                    .map(|predicate| self.eval(predicate))
+                    .collect();
+
+                if list.path.is_ident("all") {
+                    if results.contains(&Some(false)) {
+                        Some(false)
+                    } else if results.contains(&None) {
+                        None
+                    } else {
+                        Some(true)
+                    }
+                } else if list.path.is_ident("any") {
+                    if results.contains(&Some(true)) {
+                        Some(true)
+                    } else if results.contains(&None) {
+                        None
+                    } else {
+                        Some(false)
+                    }
+                } else if list.path.is_ident("not") && results.len() == 1 {
+                    results[0].map(|result| !result)
+                } else {
+                    None
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use syn::parse_quote;
+
+    /// Configuration of a build script for unix with the feature `enabled`.
+    fn cfg() -> Cfg {
+        Cfg {
+            in_build_script: true,
+            options: HashMap::from([
+                ("unix".to_owned(), Vec::new()),
+                ("target_os".to_owned(), vec!["linux".to_owned()]),
+            ]),
+            features: HashSet::from(["ENABLED".to_owned()]),
+        }
+    }
+
+    fn is_enabled(attr: Attribute) -> bool {
+        cfg().is_enabled(&[attr])
+    }
+
+    #[test]
+    fn keeps_code_on_both_sides_of_test() {
+        assert!(is_enabled(parse_quote!(#[cfg(test)])));
+        assert!(is_enabled(parse_quote!(#[cfg(not(test))])));
+        assert!(is_enabled(parse_quote!(#[cfg(all(unix, not(test)))])));
+        assert!(is_enabled(parse_quote!(#[cfg(any(windows, test))])));
+        assert!(!is_enabled(parse_quote!(#[cfg(all(windows, not(test)))])));
+        assert!(is_enabled(parse_quote!(#[cfg(any(unix, test))])));
+    }
+
+    #[test]
+    fn evaluates_all_any_and_not() {
+        assert!(is_enabled(
+            parse_quote!(#[cfg(all(unix, target_os = "linux"))])
+        ));
+        assert!(!is_enabled(parse_quote!(#[cfg(all(unix, windows))])));
+        assert!(is_enabled(parse_quote!(#[cfg(any(windows, unix))])));
+        assert!(!is_enabled(
+            parse_quote!(#[cfg(any(windows, target_os = "macos"))])
+        ));
+        assert!(is_enabled(parse_quote!(#[cfg(not(windows))])));
+        assert!(!is_enabled(parse_quote!(#[cfg(not(unix))])));
+        assert!(is_enabled(parse_quote!(#[cfg(all())])));
+        assert!(!is_enabled(parse_quote!(#[cfg(any())])));
+    }
+
+    #[test]
+    fn evaluates_features() {
+        assert!(is_enabled(parse_quote!(#[cfg(feature = "enabled")])));
+        assert!(!is_enabled(parse_quote!(#[cfg(feature = "disabled")])));
+        assert!(is_enabled(parse_quote!(#[cfg(not(feature = "disabled"))])));
+        assert!(!is_enabled(
+            parse_quote!(#[cfg(all(feature = "enabled", feature = "disabled"))])
+        ));
+    }
+
+    #[test]
+    fn keeps_code_outside_of_build_scripts() {
+        let cfg = Cfg {
+            in_build_script: false,
+            ..cfg()
+        };
+        assert!(cfg.is_enabled(&[parse_quote!(#[cfg(windows)])]));
+        assert!(cfg.is_enabled(&[parse_quote!(#[cfg(not(unix))])]));
+    }
+
+    #[test]
+    fn finds_usages_depending_on_cfg() {
+        let source = r#"
+            #[cfg(not(test))]
+            fn production() {
+                tick_metric!(production_only);
+            }
+
+            #[cfg(windows)]
+            fn windows() {
+                tick_metric!(windows_only);
+            }
+        "#;
+        let mut usages = Vec::new();
+        UsageFinder {
+            cfg: &cfg(),
+            file: Path::new("lib.rs"),
+            usages: &mut usages,
+        }
+        .find_in_source(source);
+
+        let names: Vec<_> = usages.iter().map(|usage| usage.name.as_str()).collect();
+        assert_eq!(names, ["production_only"]);
+    }
+
+    #[test]
+    fn watches_directories_new_files_are_added_to() {
+        let root = env::temp_dir().join(format!("atomic_metrics_sources_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("src/nested")).unwrap();
+        fs::write(root.join("src/lib.rs"), "").unwrap();
+        fs::write(root.join("src/nested/mod.rs"), "").unwrap();
+        let include = [format!("{}/src/**/*.rs", root.display())];
+
+        let sources = Sources::find(&include, &[]).unwrap();
+        assert_eq!(
+            sources.files,
+            BTreeSet::from([root.join("src/lib.rs"), root.join("src/nested/mod.rs")])
+        );
+        assert!(sources.dirs.contains(&root.join("src")));
+        assert!(sources.dirs.contains(&root.join("src/nested")));
+
+        // Cargo reruns the build script when a watched directory gets a new entry.
+        let added = root.join("src/nested/added.rs");
+        fs::write(&added, "fn f() { tick_metric!(added); }").unwrap();
+        assert!(sources.dirs.contains(added.parent().unwrap()));
+
+        let rerun = Sources::find(&include, &[]).unwrap();
+        assert!(rerun.files.contains(&added));
+        let usages = find_usages(&rerun).unwrap();
+        assert_eq!(usages.len(), 1);
+        assert_eq!(usages[0].name, "added");
+        assert_eq!(usages[0].file, added);
+
+        fs::remove_dir_all(&root).unwrap();
+    }
+
+    #[test]
+    fn derives_options_from_usages() {
+        let source = r#"
+            fn handle(trace_id: &str) {
+                tick_metric!(requests);
+                tick_metric_with_exemplar!(traced_requests, trace_id);
+                reset_metric!(requests);
+                set_metric!(queued, 3);
+                let _ = load_metric!(queued);
+                set_metric!(retries, 0);
+                tick_metric!(retries);
+            }
+        "#;
+        let mut usages = Vec::new();
+        UsageFinder {
+            cfg: &cfg(),
+            file: Path::new("lib.rs"),
+            usages: &mut usages,
+        }
+        .find_in_source(source);
+
+        let macros: Vec<_> = usages.iter().map(|usage| usage.macro_name).collect();
+        assert_eq!(
+            macros,
+            [
+                "tick_metric",
+                "tick_metric_with_exemplar",
+                "reset_metric",
+                "set_metric",
+                "load_metric",
+                "set_metric",
+                "tick_metric"
+            ]
+        );
+        let options = usage_options(&usages, Options::new());
+        assert!(!options.keeps_exemplars("requests"));
+        assert!(options.keeps_exemplars("traced_requests"));
+        assert_eq!(options.non_monotonic, HashSet::from(["queued".to_owned()]));
+    }
+}
@@ -0,0 +1,58 @@
+//! Exemplars linking counter increments to the traces which caused them.
+
+use crate::lock;
+use std::{sync::Mutex, time::SystemTime};
+
+/// Maximum length of the trace id of an exemplar.
+///
+/// OpenMetrics limits the labels of an exemplar to 128 characters, of which `trace_id` takes 8.
+pub const MAX_TRACE_ID_LEN: usize = 120;
+
+/// An increment of a counter together with the trace it happened in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Exemplar {
+    pub trace_id: String,
+    /// Amount the counter was incremented by.
+    pub value: u64,
+    pub timestamp: SystemTime,
+}
+
+/// Storage of the latest exemplar of a counter, see `tick_metric_with_exemplar!`.
+#[derive(Debug, Default)]
+pub struct ExemplarCell {
+    latest: Mutex<Option<Exemplar>>,
+}
+
+impl ExemplarCell {
+    pub const fn new() -> Self {
+        Self {
+            latest: Mutex::new(None),
+        }
+    }
+
+    /// Replace the stored exemplar by one for an increment by `value` in the trace `trace_id`.
+    ///
+    /// Trace ids longer than [`MAX_TRACE_ID_LEN`] are truncated. If another thread is recording
+    /// or reading at the same time, the exemplar is dropped instead of waiting for it.
+    pub fn record(&self, trace_id: impl AsRef<str>, value: u64) {
+        let Ok(mut latest) = self.latest.try_lock() else {
+            return;
+        };
+
+        let trace_id = trace_id.as_ref();
+        let end = trace_id
+            .char_indices()
+            .nth(MAX_TRACE_ID_LEN)
+            .map_or(trace_id.len(), |(idx, _)| idx);
+        *latest = Some(Exemplar {
+            trace_id: trace_id[..end].to_owned(),
+            value,
+            timestamp: SystemTime::now(),
+        });
+    }
+
+    /// Get a copy of the latest exemplar, if any was recorded.
+    pub fn get(&self) -> Option<Exemplar> {
+        lock(&self.latest).clone()
+    }
+}
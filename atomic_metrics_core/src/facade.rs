@@ -0,0 +1,391 @@
+//! Bridge routing the metrics of the [`metrics`] facade crate into the generated recorder.
+//!
+//! Libraries emitting through the facade, e.g. with `metrics::counter!("requests")`, then end up
+//! in the same exporters as the metrics of the `MetricsRecorder`:
+//!
+//! - Counters named like a counter of the recorder increment it. Labels select the series of a
+//!   labeled counter or a counter family, matched by their label names.
+//! - Gauges and histograms named like one of the recorder update it, with the values rounded to
+//!   integers.
+//! - Metrics with unknown names are registered in a fallback [`Registry`], usually the generated
+//!   `DYNAMIC_METRICS`. Counters with labels become counter families of at most
+//!   [`MAX_FALLBACK_SERIES`] series.
+//!
+//! Everything else, i.e. gauges and histograms with labels and metrics whose kind or label names
+//! do not match those of the recorder or the fallback registry, is dropped. The keys of the
+//! dropped metrics are listed by [`FacadeRecorder::dropped`]. Descriptions and units passed to
+//! the facade are ignored, those of the recorder come from the build script.
+
+use crate::{lock, AnyCounter, AnyHistogram, MetricLookup, MetricRef, Registry, ShardedCounter};
+use metrics::{
+    Counter, CounterFn, Gauge, GaugeFn, Histogram, HistogramFn, Key, KeyName, Metadata,
+    SharedString, Unit,
+};
+use std::{
+    collections::BTreeSet,
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+};
+
+/// Maximum number of series of a counter with labels registered in the fallback registry.
+pub const MAX_FALLBACK_SERIES: usize = 64;
+
+/// Implementation of [`metrics::Recorder`] updating the metrics of `recorder`.
+///
+/// ```ignore
+/// use atomic_metrics_core::facade::FacadeRecorder;
+///
+/// metrics::set_global_recorder(FacadeRecorder::new(&METRICS_RECORDER, &DYNAMIC_METRICS))?;
+/// ```
+pub struct FacadeRecorder<M: 'static> {
+    recorder: &'static M,
+    fallback: &'static Registry,
+    /// Keys of the dropped metrics.
+    dropped: Mutex<BTreeSet<String>>,
+}
+
+impl<M: 'static> FacadeRecorder<M> {
+    /// Create a bridge to `recorder`, registering unknown metrics in `fallback`.
+    pub const fn new(recorder: &'static M, fallback: &'static Registry) -> Self {
+        Self {
+            recorder,
+            fallback,
+            dropped: Mutex::new(BTreeSet::new()),
+        }
+    }
+
+    /// Keys of the facade metrics which were dropped, e.g. `jobs{queue="mail"}`, sorted.
+    ///
+    /// Always empty when metrics are compiled out.
+    pub fn dropped(&self) -> Vec<String> {
+        lock(&self.dropped).iter().cloned().collect()
+    }
+
+    /// Remember that the metric `key` was dropped.
+    fn drop_metric(&self, key: &Key) {
+        if cfg!(atomic_metrics_disabled) {
+            return;
+        }
+
+        let mut name = key.name().to_owned();
+        for (idx, label) in key.labels().enumerate() {
+            name.push(if idx == 0 { '{' } else { ',' });
+            name.push_str(&format!("{}=\"{}\"", label.key(), label.value()));
+        }
+        if key.labels().len() > 0 {
+            name.push('}');
+        }
+        lock(&self.dropped).insert(name);
+    }
+
+    /// Counter `name` of the fallback registry with `labels`, registered on first use.
+    fn fallback_counter(&self, name: &str, labels: &[(&str, &str)]) -> Option<CounterTarget> {
+        if let Some(metric) = self.fallback.get_by_name(name) {
+            return counter_target(metric, labels);
+        }
+
+        if labels.is_empty() {
+            self.fallback.counter(name).map(CounterTarget::Atomic)
+        } else {
+            let keys: Vec<&str> = labels.iter().map(|(key, _)| *key).collect();
+            let family = self.fallback.family(name, &keys, MAX_FALLBACK_SERIES)?;
+            counter_target(MetricRef::CounterFamily(family), labels)
+        }
+    }
+}
+
+impl<M: MetricLookup + Sync + 'static> metrics::Recorder for FacadeRecorder<M> {
+    fn describe_counter(&self, _key: KeyName, _unit: Option<Unit>, _description: SharedString) {}
+
+    fn describe_gauge(&self, _key: KeyName, _unit: Option<Unit>, _description: SharedString) {}
+
+    fn describe_histogram(&self, _key: KeyName, _unit: Option<Unit>, _description: SharedString) {}
+
+    fn register_counter(&self, key: &Key, _metadata: &Metadata<'_>) -> Counter {
+        let labels: Vec<_> = key
+            .labels()
+            .map(|label| (label.key(), label.value()))
+            .collect();
+
+        let target = match self.recorder.get_by_name(key.name()) {
+            Some(metric) => counter_target(metric, &labels),
+            None => self.fallback_counter(key.name(), &labels),
+        };
+
+        match target {
+            Some(target) => Counter::from_arc(Arc::new(target)),
+            None => {
+                self.drop_metric(key);
+                Counter::noop()
+            }
+        }
+    }
+
+    fn register_gauge(&self, key: &Key, _metadata: &Metadata<'_>) -> Gauge {
+        let gauge = match self.recorder.get_by_name(key.name()) {
+            Some(MetricRef::Gauge(gauge)) if key.labels().len() == 0 => Some(gauge),
+            None if key.labels().len() == 0 => self.fallback.gauge(key.name()),
+            _ => None,
+        };
+
+        match gauge {
+            Some(gauge) => Gauge::from_arc(Arc::new(GaugeTarget(gauge))),
+            None => {
+                self.drop_metric(key);
+                Gauge::noop()
+            }
+        }
+    }
+
+    fn register_histogram(&self, key: &Key, _metadata: &Metadata<'_>) -> Histogram {
+        let histogram = match self.recorder.get_by_name(key.name()) {
+            Some(MetricRef::Histogram(histogram)) if key.labels().len() == 0 => Some(histogram),
+            None if key.labels().len() == 0 => self.fallback.histogram(key.name()),
+            _ => None,
+        };
+
+        match histogram {
+            Some(histogram) => Histogram::from_arc(Arc::new(HistogramTarget(histogram))),
+            None => {
+                self.drop_metric(key);
+                Histogram::noop()
+            }
+        }
+    }
+}
+
+/// Counter of `metric` updated by a facade counter with `labels`, `None` if they do not match.
+fn counter_target(metric: MetricRef<'static>, labels: &[(&str, &str)]) -> Option<CounterTarget> {
+    match metric {
+        MetricRef::Counter(counter) if labels.is_empty() => Some(CounterTarget::Atomic(counter)),
+        MetricRef::HotCounter(counter) if labels.is_empty() => {
+            Some(CounterTarget::Sharded(counter))
+        }
+        MetricRef::CustomCounter(counter) if labels.is_empty() => {
+            Some(CounterTarget::Custom(counter))
+        }
+        MetricRef::LabeledCounter(counter) => counter.find(labels).map(CounterTarget::Atomic),
+        MetricRef::CounterFamily(family) => {
+            let values: Option<Vec<&str>> = family
+                .keys()
+                .iter()
+                .map(|key| {
+                    labels
+                        .iter()
+                        .find(|(k, _)| k == key)
+                        .map(|(_, value)| *value)
+                })
+                .collect();
+            match values {
+                Some(values) if values.len() == labels.len() => {
+                    Some(CounterTarget::Atomic(family.with(values.as_slice())))
+                }
+                _ => None,
+            }
+        }
+        _ => None,
+    }
+}
+
+/// Counter of the recorder or the fallback registry updated by a facade counter.
+enum CounterTarget {
+    Atomic(&'static AtomicU64),
+    Sharded(&'static ShardedCounter),
+    Custom(&'static dyn AnyCounter),
+}
+
+impl CounterTarget {
+    fn load(&self) -> u64 {
+        match self {
+            CounterTarget::Atomic(counter) => counter.load(Ordering::Relaxed),
+            CounterTarget::Sharded(counter) => counter.load(Ordering::Relaxed),
+            CounterTarget::Custom(counter) => counter.value(),
+        }
+    }
+}
+
+impl CounterFn for CounterTarget {
+    fn increment(&self, value: u64) {
+        match self {
+            CounterTarget::Atomic(counter) => {
+                counter.fetch_add(value, Ordering::Relaxed);
+            }
+            CounterTarget::Sharded(counter) => {
+                counter.fetch_add(value, Ordering::Relaxed);
+            }
+            CounterTarget::Custom(counter) => counter.add(value),
+        }
+    }
+
+    fn absolute(&self, value: u64) {
+        match self {
+            CounterTarget::Atomic(counter) => {
+                counter.fetch_max(value, Ordering::Relaxed);
+            }
+            // Without an atomic maximum, concurrent increments may be counted twice.
+            _ => {
+                let current = self.load();
+                if value > current {
+                    self.increment(value - current);
+                }
+            }
+        }
+    }
+}
+
+/// Gauge of the recorder updated by a facade gauge.
+struct GaugeTarget(&'static AtomicI64);
+
+impl GaugeFn for GaugeTarget {
+    fn increment(&self, value: f64) {
+        self.0.fetch_add(value.round() as i64, Ordering::Relaxed);
+    }
+
+    fn decrement(&self, value: f64) {
+        self.0.fetch_sub(value.round() as i64, Ordering::Relaxed);
+    }
+
+    fn set(&self, value: f64) {
+        self.0.store(value.round() as i64, Ordering::Relaxed);
+    }
+}
+
+/// Histogram of the recorder updated by a facade histogram.
+struct HistogramTarget(&'static dyn AnyHistogram);
+
+impl HistogramFn for HistogramTarget {
+    fn record(&self, value: f64) {
+        // Negative values are recorded as zero, the cast saturates.
+        self.0.observe(value.round() as u64);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use metrics::Recorder;
+
+    static REQUESTS: AtomicU64 = AtomicU64::new(0);
+    static LEVEL: AtomicI64 = AtomicI64::new(0);
+
+    struct Lookup;
+
+    impl MetricLookup for Lookup {
+        fn get_by_name(&self, name: &str) -> Option<MetricRef<'_>> {
+            match name {
+                "requests" => Some(MetricRef::Counter(&REQUESTS)),
+                "level" => Some(MetricRef::Gauge(&LEVEL)),
+                _ => None,
+            }
+        }
+    }
+
+    static LOOKUP: Lookup = Lookup;
+
+    fn metadata() -> Metadata<'static> {
+        Metadata::new("test", metrics::Level::INFO, None)
+    }
+
+    #[test]
+    fn updates_metrics_of_the_recorder() {
+        static FALLBACK: Registry = Registry::new(&[]);
+        let recorder = FacadeRecorder::new(&LOOKUP, &FALLBACK);
+
+        recorder
+            .register_counter(&Key::from_name("requests"), &metadata())
+            .increment(2);
+        recorder
+            .register_gauge(&Key::from_name("level"), &metadata())
+            .set(-3.0);
+
+        assert_eq!(REQUESTS.load(Ordering::Relaxed), 2);
+        assert_eq!(LEVEL.load(Ordering::Relaxed), -3);
+        assert_eq!(FALLBACK.snapshot(), Default::default());
+        assert!(recorder.dropped().is_empty());
+    }
+
+    #[test]
+    #[cfg(not(atomic_metrics_disabled))]
+    fn registers_unknown_metrics_in_the_fallback() {
+        static FALLBACK: Registry = Registry::new(&[]);
+        let recorder = FacadeRecorder::new(&LOOKUP, &FALLBACK);
+        let mail = Key::from_parts(
+            "jobs",
+            vec![
+                metrics::Label::new("queue", "mail"),
+                metrics::Label::new("state", "done"),
+            ],
+        );
+        let reordered = Key::from_parts(
+            "jobs",
+            vec![
+                metrics::Label::new("state", "done"),
+                metrics::Label::new("queue", "mail"),
+            ],
+        );
+
+        recorder
+            .register_counter(&Key::from_name("plugin_loads"), &metadata())
+            .increment(1);
+        recorder.register_counter(&mail, &metadata()).increment(2);
+        recorder
+            .register_counter(&reordered, &metadata())
+            .increment(3);
+        recorder
+            .register_gauge(&Key::from_name("queue_depth"), &metadata())
+            .set(4.0);
+        recorder
+            .register_histogram(&Key::from_name("load_ms"), &metadata())
+            .record(7.0);
+
+        let snapshot = FALLBACK.snapshot();
+        assert_eq!(snapshot.counters[0].0.name, "plugin_loads");
+        assert_eq!(snapshot.counters[0].1, 1);
+        assert_eq!(snapshot.families[0].1.keys, ["queue", "state"]);
+        assert_eq!(
+            snapshot.families[0].1.series,
+            [(vec!["mail".to_owned(), "done".to_owned()], 5)]
+        );
+        assert_eq!(snapshot.gauges[0].1, 4);
+        assert_eq!(snapshot.histograms[0].1.sum, 7);
+        assert!(recorder.dropped().is_empty());
+    }
+
+    #[test]
+    #[cfg(not(atomic_metrics_disabled))]
+    fn lists_dropped_metrics() {
+        static FALLBACK: Registry = Registry::new(&[]);
+        let recorder = FacadeRecorder::new(&LOOKUP, &FALLBACK);
+        let queued = Key::from_parts("queue_depth", vec![metrics::Label::new("queue", "mail")]);
+        let labeled = Key::from_parts("requests", vec![metrics::Label::new("method", "get")]);
+
+        for _ in 0..2 {
+            let _ = recorder.register_gauge(&queued, &metadata());
+            let _ = recorder.register_counter(&labeled, &metadata());
+            let _ = recorder.register_histogram(&Key::from_name("requests"), &metadata());
+            let _ = recorder.register_gauge(&Key::from_name("plugin_loads"), &metadata());
+        }
+        let _ = recorder.register_counter(&Key::from_name("plugin_loads"), &metadata());
+
+        assert_eq!(
+            recorder.dropped(),
+            [
+                "plugin_loads",
+                "queue_depth{queue=\"mail\"}",
+                "requests",
+                "requests{method=\"get\"}"
+            ]
+        );
+    }
+
+    #[test]
+    #[cfg(atomic_metrics_disabled)]
+    fn drops_nothing_when_disabled() {
+        static FALLBACK: Registry = Registry::new(&[]);
+        let recorder = FacadeRecorder::new(&LOOKUP, &FALLBACK);
+        let _ = recorder.register_gauge(&Key::from_name("queue_depth"), &metadata());
+        assert!(recorder.dropped().is_empty());
+    }
+}
@@ -0,0 +1,323 @@
+//! Counters with label values only known at runtime, with a bound on the number of series.
+
+use crate::{read, write, Delta, MetricInfo, Visitor};
+use std::{
+    borrow::Borrow,
+    collections::HashMap,
+    hash::{BuildHasherDefault, DefaultHasher, Hash, Hasher},
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        RwLock,
+    },
+};
+
+/// Label value of the series counting all increments beyond the cardinality cap.
+pub const OVERFLOW_LABEL: &str = "__overflow__";
+
+/// Label values identifying one series of a [`CounterFamily`], e.g. a tuple of `&str`.
+pub trait FamilyLabels {
+    /// Number of label values.
+    fn count(&self) -> usize;
+
+    /// Label value at position `idx`.
+    fn value(&self, idx: usize) -> &str;
+}
+
+macro_rules! impl_family_labels {
+    ($count:literal; $($label:ident $idx:tt),+) => {
+        impl<$($label: AsRef<str>),+> FamilyLabels for ($($label,)+) {
+            fn count(&self) -> usize {
+                $count
+            }
+
+            fn value(&self, idx: usize) -> &str {
+                match idx {
+                    $($idx => self.$idx.as_ref(),)+
+                    _ => panic!("label index {idx} out of bounds"),
+                }
+            }
+        }
+    };
+}
+
+impl_family_labels!(1; A 0);
+impl_family_labels!(2; A 0, B 1);
+impl_family_labels!(3; A 0, B 1, C 2);
+impl_family_labels!(4; A 0, B 1, C 2, D 3);
+
+impl<S: AsRef<str>, const N: usize> FamilyLabels for [S; N] {
+    fn count(&self) -> usize {
+        N
+    }
+
+    fn value(&self, idx: usize) -> &str {
+        self[idx].as_ref()
+    }
+}
+
+impl<S: AsRef<str>> FamilyLabels for &[S] {
+    fn count(&self) -> usize {
+        self.len()
+    }
+
+    fn value(&self, idx: usize) -> &str {
+        self[idx].as_ref()
+    }
+}
+
+impl Hash for dyn FamilyLabels + '_ {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        for idx in 0..self.count() {
+            self.value(idx).hash(state);
+        }
+    }
+}
+
+impl PartialEq for dyn FamilyLabels + '_ {
+    fn eq(&self, other: &Self) -> bool {
+        self.count() == other.count()
+            && (0..self.count()).all(|idx| self.value(idx) == other.value(idx))
+    }
+}
+
+impl Eq for dyn FamilyLabels + '_ {}
+
+/// Owned label values of a series, which can be looked up by any [`FamilyLabels`] without
+/// allocating.
+struct SeriesLabels(Box<[String]>);
+
+impl FamilyLabels for SeriesLabels {
+    fn count(&self) -> usize {
+        self.0.len()
+    }
+
+    fn value(&self, idx: usize) -> &str {
+        &self.0[idx]
+    }
+}
+
+impl<'a> Borrow<dyn FamilyLabels + 'a> for SeriesLabels {
+    fn borrow(&self) -> &(dyn FamilyLabels + 'a) {
+        self
+    }
+}
+
+impl Hash for SeriesLabels {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        (self as &dyn FamilyLabels).hash(state);
+    }
+}
+
+impl PartialEq for SeriesLabels {
+    fn eq(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl Eq for SeriesLabels {}
+
+type SeriesMap = HashMap<SeriesLabels, &'static AtomicU64, BuildHasherDefault<DefaultHasher>>;
+
+/// Family of counters keyed by label values which are only known at runtime, e.g. a tenant id.
+///
+/// At most `max_series` distinct label combinations get their own series. Increments for any
+/// further combination go to a single overflow series with all labels set to
+/// [`OVERFLOW_LABEL`], so a misbehaving caller cannot grow the family without bound.
+///
+/// The counters of the series are allocated once and never freed, which keeps the borrows
+/// returned by [`CounterFamily::with`] valid without holding a lock. The family is meant to live
+/// in a `static` like the generated `METRICS_RECORDER`.
+pub struct CounterFamily {
+    keys: &'static [&'static str],
+    max_series: usize,
+    series: RwLock<SeriesMap>,
+    overflow: AtomicU64,
+}
+
+impl CounterFamily {
+    /// Create a family with the label names `keys` and at most `max_series` series.
+    pub const fn new(keys: &'static [&'static str], max_series: usize) -> Self {
+        Self {
+            keys,
+            max_series,
+            series: RwLock::new(HashMap::with_hasher(BuildHasherDefault::new())),
+            overflow: AtomicU64::new(0),
+        }
+    }
+
+    /// Names of the labels.
+    pub fn keys(&self) -> &'static [&'static str] {
+        self.keys
+    }
+
+    /// Borrow the atomic value of the series with the label values `labels`.
+    ///
+    /// The series is created on first use. If the family already has `max_series` series or the
+    /// number of label values does not match the number of keys, the overflow series is returned.
+    pub fn with(&self, labels: impl FamilyLabels) -> &AtomicU64 {
+        let labels: &dyn FamilyLabels = &labels;
+        if labels.count() != self.keys.len() {
+            debug_assert!(false, "wrong number of label values for {:?}", self.keys);
+            return &self.overflow;
+        }
+
+        if let Some(counter) = read(&self.series).get(labels) {
+            return counter;
+        }
+
+        let mut series = write(&self.series);
+        if let Some(counter) = series.get(labels) {
+            return counter;
+        }
+        if series.len() >= self.max_series {
+            return &self.overflow;
+        }
+
+        let counter = Box::leak(Box::new(AtomicU64::new(0)));
+        let owned = (0..labels.count())
+            .map(|idx| labels.value(idx).to_owned())
+            .collect();
+        series.insert(SeriesLabels(owned), counter);
+        counter
+    }
+
+    /// Load the values of all series, sorted by their label values.
+    pub fn snapshot(&self) -> FamilySnapshot {
+        let mut series: Vec<_> = read(&self.series)
+            .iter()
+            .map(|(labels, counter)| (labels.0.to_vec(), counter.load(Ordering::Relaxed)))
+            .collect();
+        series.sort_unstable();
+
+        FamilySnapshot {
+            keys: self.keys,
+            series,
+            overflow: self.overflow.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Reset all series and the overflow series to zero.
+    ///
+    /// The series are kept and still count towards `max_series`.
+    pub fn reset(&self) {
+        for counter in read(&self.series).values() {
+            counter.store(0, Ordering::Relaxed);
+        }
+        self.overflow.store(0, Ordering::Relaxed);
+    }
+}
+
+/// Values of all series of a [`CounterFamily`] at one point in time.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct FamilySnapshot {
+    /// Names of the labels.
+    pub keys: &'static [&'static str],
+    /// Label values and value of every series, sorted by the label values.
+    pub series: Vec<(Vec<String>, u64)>,
+    /// Value of the overflow series.
+    pub overflow: u64,
+}
+
+impl FamilySnapshot {
+    /// Pass every series as a counter described by `info` to `visitor`.
+    ///
+    /// The overflow series is only visited once it was incremented.
+    pub fn visit(&self, info: &MetricInfo, visitor: &mut dyn Visitor) {
+        let mut labels = Vec::with_capacity(self.keys.len());
+        for (values, value) in self.series.iter() {
+            labels.clear();
+            labels.extend(
+                self.keys
+                    .iter()
+                    .copied()
+                    .zip(values.iter().map(String::as_str)),
+            );
+            visitor.counter(info, &labels, *value);
+        }
+
+        if self.overflow > 0 {
+            labels.clear();
+            labels.extend(self.keys.iter().map(|key| (*key, OVERFLOW_LABEL)));
+            visitor.counter(info, &labels, self.overflow);
+        }
+    }
+}
+
+impl Delta for FamilySnapshot {
+    fn delta(&self, previous: &Self) -> Self {
+        FamilySnapshot {
+            keys: self.keys,
+            series: self
+                .series
+                .iter()
+                .map(|(labels, value)| {
+                    let increment = match previous
+                        .series
+                        .binary_search_by(|(other, _)| other.cmp(labels))
+                    {
+                        Ok(idx) => value.delta(&previous.series[idx].1),
+                        Err(_) => *value,
+                    };
+                    (labels.clone(), increment)
+                })
+                .collect(),
+            overflow: self.overflow.delta(&previous.overflow),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ptr;
+
+    #[test]
+    fn returns_the_same_series_for_the_same_labels() {
+        let family = CounterFamily::new(&["tenant", "region"], 10);
+        let counter = family.with(("a", "eu"));
+        counter.fetch_add(1, Ordering::Relaxed);
+
+        assert!(ptr::eq(counter, family.with(("a", "eu"))));
+        assert!(ptr::eq(counter, family.with(["a", "eu"])));
+        assert!(ptr::eq(
+            counter,
+            family.with(&["a".to_owned(), "eu".to_owned()][..])
+        ));
+        assert!(!ptr::eq(counter, family.with(("a", "us"))));
+        assert!(!ptr::eq(counter, family.with(("eu", "a"))));
+        assert_eq!(family.with(("a", "eu")).load(Ordering::Relaxed), 1);
+    }
+
+    #[test]
+    fn counts_series_beyond_the_cap_in_the_overflow_series() {
+        let family = CounterFamily::new(&["tenant"], 2);
+        family.with(("a",)).fetch_add(1, Ordering::Relaxed);
+        family.with(("b",)).fetch_add(2, Ordering::Relaxed);
+        family.with(("c",)).fetch_add(3, Ordering::Relaxed);
+        family.with(("d",)).fetch_add(4, Ordering::Relaxed);
+        // Series created before the cap was reached keep counting.
+        family.with(("a",)).fetch_add(5, Ordering::Relaxed);
+
+        assert!(ptr::eq(family.with(("c",)), family.with(("d",))));
+        assert_eq!(
+            family.snapshot(),
+            FamilySnapshot {
+                keys: &["tenant"],
+                series: vec![(vec!["a".to_owned()], 6), (vec!["b".to_owned()], 2)],
+                overflow: 7,
+            }
+        );
+    }
+
+    #[test]
+    fn keeps_reset_series_towards_the_cap() {
+        let family = CounterFamily::new(&["tenant"], 1);
+        family.with(("a",)).fetch_add(1, Ordering::Relaxed);
+        family.reset();
+        family.with(("b",)).fetch_add(1, Ordering::Relaxed);
+
+        let snapshot = family.snapshot();
+        assert_eq!(snapshot.series, vec![(vec!["a".to_owned()], 0)]);
+        assert_eq!(snapshot.overflow, 1);
+    }
+}
@@ -0,0 +1,1116 @@
+use crate::{AtomicType, MetricsBuilder};
+use anyhow::{bail, Result};
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    fmt,
+    io::Write,
+};
+
+/// Kind of a metric, determining the atomic type backing it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MetricKind {
+    /// Unsigned counter backed by an `AtomicU64`.
+    Counter,
+    /// Signed value going up and down, backed by an `AtomicI64`.
+    Gauge,
+    /// Distribution of observed values, backed by a [`Histogram`](crate::Histogram).
+    Histogram,
+}
+
+impl fmt::Display for MetricKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MetricKind::Counter => write!(f, "counter"),
+            MetricKind::Gauge => write!(f, "gauge"),
+            MetricKind::Histogram => write!(f, "histogram"),
+        }
+    }
+}
+
+/// A metric of the generated `MetricsRecorder`.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Metric {
+    pub name: String,
+    pub kind: MetricKind,
+    /// Description of what is measured.
+    pub help: Option<String>,
+    /// Unit of the measured values, e.g. `seconds` or `bytes`.
+    pub unit: Option<String>,
+}
+
+impl Metric {
+    pub fn new(name: impl Into<String>, kind: MetricKind) -> Self {
+        Self {
+            name: name.into(),
+            kind,
+            help: None,
+            unit: None,
+        }
+    }
+}
+
+/// Options for generating the `MetricsRecorder`.
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    histogram_buckets: HashMap<String, Vec<u64>>,
+    help: HashMap<String, String>,
+    units: HashMap<String, String>,
+    hot: HashSet<String>,
+    exemplars: HashSet<String>,
+    pub(crate) non_monotonic: HashSet<String>,
+    label_enums: BTreeMap<String, Vec<String>>,
+    labels: HashMap<String, Vec<String>>,
+    families: HashMap<String, (Vec<String>, usize)>,
+}
+
+impl Options {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Declare the inclusive upper `bounds` of the buckets of the histogram `name`.
+    ///
+    /// Histograms without declared bounds use [`DEFAULT_BUCKETS`](crate::DEFAULT_BUCKETS).
+    pub fn histogram_buckets(mut self, name: &str, bounds: &[u64]) -> Self {
+        self.histogram_buckets
+            .insert(name.to_owned(), bounds.to_vec());
+        self
+    }
+
+    /// Attach the description `help` to the metric `name`.
+    pub fn help(mut self, name: &str, help: &str) -> Self {
+        self.help.insert(name.to_owned(), help.to_owned());
+        self
+    }
+
+    /// Attach the `unit` of the measured values, e.g. `seconds` or `bytes`, to the metric `name`.
+    pub fn unit(mut self, name: &str, unit: &str) -> Self {
+        self.units.insert(name.to_owned(), unit.to_owned());
+        self
+    }
+
+    /// Back the counter `name` by a [`ShardedCounter`](crate::ShardedCounter).
+    ///
+    /// This avoids contention when many threads increment the counter at the same time, at the
+    /// cost of more memory and more expensive loads.
+    pub fn hot(mut self, name: &str) -> Self {
+        self.hot.insert(name.to_owned());
+        self
+    }
+
+    /// Keep the latest exemplar of the counter `name` for `tick_metric_with_exemplar!`.
+    ///
+    /// Counters discovered in usages of `tick_metric_with_exemplar!` keep exemplars without
+    /// declaring them here. Counters with labels cannot keep exemplars.
+    pub fn exemplars(mut self, name: &str) -> Self {
+        self.exemplars.insert(name.to_owned());
+        self
+    }
+
+    /// Mark the counter `name` as not monotonic, since it is only set to arbitrary values.
+    ///
+    /// Counters whose only updates are `set_metric!` usages are marked without declaring them
+    /// here. Exporters expose these counters as gauges.
+    pub fn non_monotonic(mut self, name: &str) -> Self {
+        self.non_monotonic.insert(name.to_owned());
+        self
+    }
+
+    /// Declare a fieldless label enum `name` with the given `variants`.
+    ///
+    /// The enum is generated next to the `MetricsRecorder`. In exported series, the label key is
+    /// the snake case name of the enum and the label values are the snake case variant names,
+    /// e.g. `Method::Get` becomes `method="get"`.
+    pub fn label_enum(mut self, name: &str, variants: &[&str]) -> Self {
+        self.label_enums.insert(
+            name.to_owned(),
+            variants.iter().map(|variant| variant.to_string()).collect(),
+        );
+        self
+    }
+
+    /// Split the counter `name` into one series per combination of the values of the label
+    /// enums `labels`, see [`LabeledCounter`](crate::LabeledCounter).
+    ///
+    /// The macros then take the labels after the metric name and the value, e.g.
+    /// `tick_metric!(http_requests, Method::Get, Status::Ok)`.
+    pub fn labels(mut self, name: &str, labels: &[&str]) -> Self {
+        self.labels.insert(
+            name.to_owned(),
+            labels.iter().map(|label| label.to_string()).collect(),
+        );
+        self
+    }
+
+    /// Back the counter `name` by a [`CounterFamily`](crate::CounterFamily) with the label names
+    /// `keys`, whose values are only known at runtime.
+    ///
+    /// At most `max_series` label combinations get their own series, all further ones are counted
+    /// in an overflow series. The macros take the label values like those of labeled counters,
+    /// e.g. `tick_metric!(tenant_requests, tenant_id)`.
+    pub fn dynamic_labels(mut self, name: &str, keys: &[&str], max_series: usize) -> Self {
+        self.families.insert(
+            name.to_owned(),
+            (keys.iter().map(|key| key.to_string()).collect(), max_series),
+        );
+        self
+    }
+
+    fn validate(&self, metrics: &[Metric]) -> Result<()> {
+        for (name, (keys, max_series)) in self.families.iter() {
+            if !metrics
+                .iter()
+                .any(|metric| metric.name == *name && metric.kind == MetricKind::Counter)
+            {
+                bail!("dynamic labels declared for `{name}` which is not a counter");
+            }
+            if self.hot.contains(name) || self.labels.contains_key(name) {
+                bail!("counter `{name}` with dynamic labels cannot be hot or have static labels");
+            }
+            if keys.is_empty() || keys.len() > 4 {
+                bail!("counter `{name}` needs between one and four dynamic labels");
+            }
+            for (idx, key) in keys.iter().enumerate() {
+                if syn::parse_str::<syn::Ident>(key).is_err() {
+                    bail!("dynamic label `{key}` of counter `{name}` is not a valid identifier");
+                }
+                if keys[..idx].contains(key) {
+                    bail!("dynamic label `{key}` is used twice by counter `{name}`");
+                }
+            }
+            if *max_series == 0 {
+                bail!("counter `{name}` needs a maximum number of series above zero");
+            }
+        }
+        for (name, variants) in self.label_enums.iter() {
+            if syn::parse_str::<syn::Ident>(name).is_err() {
+                bail!("label enum name `{name}` is not a valid identifier");
+            }
+            if variants.is_empty() {
+                bail!("label enum `{name}` needs at least one variant");
+            }
+            for (idx, variant) in variants.iter().enumerate() {
+                if syn::parse_str::<syn::Ident>(variant).is_err() {
+                    bail!("variant `{variant}` of label enum `{name}` is not a valid identifier");
+                }
+                if variants[..idx].contains(variant) {
+                    bail!("variant `{variant}` of label enum `{name}` is declared twice");
+                }
+            }
+        }
+        for (name, labels) in self.labels.iter() {
+            if !metrics
+                .iter()
+                .any(|metric| metric.name == *name && metric.kind == MetricKind::Counter)
+            {
+                bail!("labels declared for `{name}` which is not a counter");
+            }
+            if self.hot.contains(name) {
+                bail!("counter `{name}` cannot be both hot and labeled");
+            }
+            if labels.is_empty() || labels.len() > 4 {
+                bail!("counter `{name}` needs between one and four labels");
+            }
+            for (idx, label) in labels.iter().enumerate() {
+                if !self.label_enums.contains_key(label) {
+                    bail!("label `{label}` of counter `{name}` is not a declared label enum");
+                }
+                if labels[..idx].contains(label) {
+                    bail!("label `{label}` is used twice by counter `{name}`");
+                }
+            }
+        }
+
+        for name in self.hot.iter() {
+            if !metrics
+                .iter()
+                .any(|metric| metric.name == *name && metric.kind == MetricKind::Counter)
+            {
+                bail!("`{name}` is declared as hot but is not a counter");
+            }
+        }
+        for metric in metrics {
+            if metric.name.starts_with("__") {
+                bail!(
+                    "metric name `{}` is reserved, names must not start with `__`",
+                    metric.name
+                );
+            }
+            if metric.name == "dynamic_metrics" {
+                bail!("metric name `dynamic_metrics` is reserved for the runtime registry");
+            }
+        }
+        for name in self.exemplars.iter() {
+            if !metrics
+                .iter()
+                .any(|metric| metric.name == *name && metric.kind == MetricKind::Counter)
+                || self.labels.contains_key(name)
+                || self.families.contains_key(name)
+            {
+                bail!("`{name}` keeps exemplars but is not a counter without labels");
+            }
+        }
+        for name in self.non_monotonic.iter() {
+            if !metrics
+                .iter()
+                .any(|metric| metric.name == *name && metric.kind == MetricKind::Counter)
+            {
+                bail!("`{name}` is declared as not monotonic but is not a counter");
+            }
+        }
+        for name in self.help.keys().chain(self.units.keys()) {
+            if !metrics.iter().any(|metric| metric.name == *name) {
+                bail!("metadata declared for unknown metric `{name}`");
+            }
+        }
+        for (name, bounds) in self.histogram_buckets.iter() {
+            if !metrics
+                .iter()
+                .any(|metric| metric.name == *name && metric.kind == MetricKind::Histogram)
+            {
+                bail!("buckets declared for `{name}` which is not a histogram");
+            }
+            if bounds.is_empty() {
+                bail!("histogram `{name}` needs at least one bucket bound");
+            }
+            if bounds.windows(2).any(|pair| pair[0] >= pair[1]) {
+                bail!("bucket bounds of histogram `{name}` are not strictly ascending");
+            }
+        }
+
+        Ok(())
+    }
+
+    fn buckets(&self, name: &str) -> &[u64] {
+        self.histogram_buckets
+            .get(name)
+            .map(|bounds| bounds.as_slice())
+            .unwrap_or(crate::DEFAULT_BUCKETS)
+    }
+
+    /// Whether the counter `name` keeps an exemplar.
+    pub(crate) fn keeps_exemplars(&self, name: &str) -> bool {
+        self.exemplars.contains(name)
+    }
+
+    /// Type of the field backing the counter `name` if it is labeled.
+    fn labeled_counter(&self, name: &str) -> Option<String> {
+        let labels = self.labels.get(name)?;
+        let cardinality: usize = labels
+            .iter()
+            .map(|label| self.label_enums[label].len())
+            .product();
+
+        Some(format!(
+            "atomic_metrics_core::LabeledCounter<({},), {cardinality}>",
+            labels.join(", ")
+        ))
+    }
+}
+
+/// Generate the global `MetricsRecorder` based on all metrics usages in the source directory.
+pub fn generate_metrics_recorder() -> Result<()> {
+    MetricsBuilder::new().generate()
+}
+
+/// Generate the global `MetricsRecorder` based on all metrics usages in the source directory,
+/// customized by `options`.
+pub fn generate_metrics_recorder_with_options(options: &Options) -> Result<()> {
+    MetricsBuilder::new().options(options.clone()).generate()
+}
+
+/// Generate the global `MetricsRecorder` with all the metrics names passed as counters.
+///
+/// There will be a compilation error if you try to access/modify a metric not mentioned here.
+pub fn generate_metrics_recorder_with_names<'a>(
+    metric_names: impl Iterator<Item = &'a str>,
+) -> Result<()> {
+    let metrics: Vec<_> = metric_names
+        .map(|name| Metric::new(name, MetricKind::Counter))
+        .collect();
+
+    generate_metrics_recorder_with_metrics(&metrics, &Options::default())
+}
+
+/// Generate the global `MetricsRecorder` with all the metrics passed.
+///
+/// There will be a compilation error if you try to access/modify a metric not mentioned here.
+pub fn generate_metrics_recorder_with_metrics(metrics: &[Metric], options: &Options) -> Result<()> {
+    MetricsBuilder::new()
+        .options(options.clone())
+        .metrics(metrics)
+        .generate()
+}
+
+/// Write the code of the recorder configured by `builder` with `metrics` to `out`.
+///
+/// When metrics are `disabled`, the recorder is written without any metrics.
+pub(crate) fn write_metrics(
+    out: &mut impl Write,
+    builder: &MetricsBuilder,
+    metrics: &[Metric],
+    disabled: bool,
+) -> Result<()> {
+    let options = &builder.options;
+    let metrics = if disabled {
+        &[]
+    } else {
+        options.validate(metrics)?;
+        metrics
+    };
+
+    let metrics: Vec<_> = metrics
+        .iter()
+        .map(|metric| Metric {
+            help: options
+                .help
+                .get(&metric.name)
+                .or(metric.help.as_ref())
+                .cloned(),
+            unit: options
+                .units
+                .get(&metric.name)
+                .or(metric.unit.as_ref())
+                .cloned(),
+            ..metric.clone()
+        })
+        .collect();
+
+    let MetricsBuilder {
+        type_name: ty,
+        static_name,
+        visibility: vis,
+        ..
+    } = builder;
+
+    write_label_enums(out, builder)?;
+    write_recorder(out, builder, &metrics)?;
+    write_metric_id(out, builder, &metrics)?;
+    write_snapshot(out, builder, &metrics)?;
+
+    writeln!(out, "{vis} static {static_name}: {ty} = {ty}::new();")?;
+    writeln!(out)?;
+    writeln!(
+        out,
+        "/// Metrics registered at runtime, included in the snapshots of the `{ty}`."
+    )?;
+    writeln!(
+        out,
+        "{vis} static {}: atomic_metrics_core::Registry =",
+        builder.registry_name()
+    )?;
+    writeln!(out, "atomic_metrics_core::Registry::new({ty}::METADATA);")?;
+
+    Ok(())
+}
+
+/// Write the declared label enums with their `LabelValue` implementations.
+///
+/// The enums are written even when metrics are compiled out, since the macros still type-check
+/// their label arguments.
+fn write_label_enums(out: &mut impl Write, builder: &MetricsBuilder) -> Result<()> {
+    let MetricsBuilder {
+        options,
+        visibility: vis,
+        ..
+    } = builder;
+
+    for (name, variants) in options.label_enums.iter() {
+        writeln!(out, "#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]")?;
+        writeln!(out, "{vis} enum {name} {{")?;
+        for variant in variants {
+            writeln!(out, "{variant},")?;
+        }
+        writeln!(out, "}}")?;
+        writeln!(out)?;
+
+        writeln!(out, "impl atomic_metrics_core::LabelValue for {name} {{")?;
+        writeln!(out, "const KEY: &'static str = {:?};", snake_case(name))?;
+        let values: Vec<_> = variants.iter().map(|variant| snake_case(variant)).collect();
+        writeln!(out, "const VALUES: &'static [&'static str] = &{values:?};")?;
+        writeln!(out)?;
+        writeln!(out, "fn index(self) -> usize {{")?;
+        writeln!(out, "self as usize")?;
+        writeln!(out, "}}")?;
+        writeln!(out, "}}")?;
+        writeln!(out)?;
+    }
+
+    Ok(())
+}
+
+/// Convert a camel case identifier like `StatusClass` to snake case like `status_class`.
+pub(crate) fn snake_case(name: &str) -> String {
+    let chars: Vec<char> = name.chars().collect();
+    let mut snake = String::with_capacity(name.len() + 4);
+    for (idx, &c) in chars.iter().enumerate() {
+        if c.is_uppercase() && idx > 0 {
+            let previous = chars[idx - 1];
+            let next_is_lower = chars.get(idx + 1).is_some_and(|next| next.is_lowercase());
+            if previous.is_lowercase()
+                || previous.is_ascii_digit()
+                || (previous.is_uppercase() && next_is_lower)
+            {
+                snake.push('_');
+            }
+        }
+        snake.extend(c.to_lowercase());
+    }
+    snake
+}
+
+/// Write the `MetricsRecorder` struct holding the atomic metrics.
+fn write_recorder(
+    out: &mut impl Write,
+    builder: &MetricsBuilder,
+    metrics: &[Metric],
+) -> Result<()> {
+    let MetricsBuilder {
+        type_name: ty,
+        visibility: vis,
+        options,
+        ..
+    } = builder;
+    let snapshot = builder.snapshot_name();
+    let registry = builder.registry_name();
+    let atomic = builder.atomic_type.name();
+    let exemplars = metrics
+        .iter()
+        .any(|metric| options.keeps_exemplars(&metric.name));
+
+    writeln!(out, "{vis} struct {ty} {{")?;
+
+    for Metric {
+        name,
+        kind,
+        help,
+        unit,
+    } in metrics
+    {
+        if let Some(help) = help {
+            for line in help.lines() {
+                writeln!(out, "/// {line}")?;
+            }
+        }
+        if let Some(unit) = unit {
+            if help.is_some() {
+                writeln!(out, "///")?;
+            }
+            writeln!(out, "/// Unit: {unit}")?;
+        }
+        match kind {
+            MetricKind::Counter if options.hot.contains(name) => {
+                writeln!(out, "pub {name}: atomic_metrics_core::ShardedCounter,")?
+            }
+            MetricKind::Counter if options.labels.contains_key(name) => writeln!(
+                out,
+                "pub {name}: {},",
+                options.labeled_counter(name).unwrap_or_default()
+            )?,
+            MetricKind::Counter if options.families.contains_key(name) => {
+                writeln!(out, "pub {name}: atomic_metrics_core::CounterFamily,")?
+            }
+            MetricKind::Counter => writeln!(out, "pub {name}: std::sync::atomic::{atomic},")?,
+            MetricKind::Gauge => writeln!(out, "pub {name}: std::sync::atomic::AtomicI64,")?,
+            MetricKind::Histogram => writeln!(
+                out,
+                "pub {name}: atomic_metrics_core::Histogram<{}>,",
+                options.buckets(name).len() + 1
+            )?,
+        }
+    }
+
+    if !metrics.is_empty() {
+        writeln!(out, "__snapshot_lock: atomic_metrics_core::SnapshotLock,")?;
+    }
+    if exemplars {
+        writeln!(out, "#[doc(hidden)]")?;
+        writeln!(out, "pub __exemplars: {ty}Exemplars,")?;
+    }
+    writeln!(out, "}}")?;
+    writeln!(out)?;
+
+    if exemplars {
+        writeln!(
+            out,
+            "/// Latest exemplars of the counters used with `tick_metric_with_exemplar!`."
+        )?;
+        writeln!(out, "#[doc(hidden)]")?;
+        writeln!(out, "{vis} struct {ty}Exemplars {{")?;
+        for Metric { name, .. } in metrics {
+            if options.keeps_exemplars(name) {
+                writeln!(out, "pub {name}: atomic_metrics_core::ExemplarCell,")?;
+            }
+        }
+        writeln!(out, "}}")?;
+        writeln!(out)?;
+    }
+
+    writeln!(out, "#[allow(dead_code)]")?;
+    writeln!(out, "impl {ty} {{")?;
+    writeln!(out, "pub const fn new() -> Self {{")?;
+    writeln!(out, "Self {{")?;
+
+    for Metric { name, kind, .. } in metrics {
+        match kind {
+            MetricKind::Counter if options.hot.contains(name) => {
+                writeln!(out, "{name}: atomic_metrics_core::ShardedCounter::new(0),")?
+            }
+            MetricKind::Counter if options.labels.contains_key(name) => {
+                writeln!(out, "{name}: atomic_metrics_core::LabeledCounter::new(),")?
+            }
+            MetricKind::Counter if options.families.contains_key(name) => {
+                let (keys, max_series) = &options.families[name];
+                writeln!(
+                    out,
+                    "{name}: atomic_metrics_core::CounterFamily::new(&{keys:?}, {max_series}),"
+                )?
+            }
+            MetricKind::Counter => writeln!(out, "{name}: std::sync::atomic::{atomic}::new(0),")?,
+            MetricKind::Gauge => writeln!(out, "{name}: std::sync::atomic::AtomicI64::new(0),")?,
+            MetricKind::Histogram => writeln!(
+                out,
+                "{name}: atomic_metrics_core::Histogram::new(&{:?}),",
+                options.buckets(name)
+            )?,
+        }
+    }
+
+    if !metrics.is_empty() {
+        writeln!(
+            out,
+            "__snapshot_lock: atomic_metrics_core::SnapshotLock::new(),"
+        )?;
+    }
+    if exemplars {
+        writeln!(out, "__exemplars: {ty}Exemplars {{")?;
+        for Metric { name, .. } in metrics {
+            if options.keeps_exemplars(name) {
+                writeln!(out, "{name}: atomic_metrics_core::ExemplarCell::new(),")?;
+            }
+        }
+        writeln!(out, "}},")?;
+    }
+    writeln!(out, "}}")?;
+    writeln!(out, "}}")?;
+    writeln!(out)?;
+
+    writeln!(
+        out,
+        "/// Description of all metrics, in the order of the fields."
+    )?;
+    writeln!(
+        out,
+        "pub const METADATA: &'static [atomic_metrics_core::MetricInfo] = &["
+    )?;
+
+    for metric in metrics {
+        writeln!(out, "atomic_metrics_core::MetricInfo {{")?;
+        writeln!(out, "name: {:?},", metric.name)?;
+        writeln!(
+            out,
+            "kind: atomic_metrics_core::MetricKind::{:?},",
+            metric.kind
+        )?;
+        writeln!(out, "help: {:?},", metric.help)?;
+        writeln!(out, "unit: {:?},", metric.unit)?;
+        writeln!(
+            out,
+            "monotonic: {},",
+            metric.kind != MetricKind::Gauge && !options.non_monotonic.contains(&metric.name)
+        )?;
+        writeln!(out, "}},")?;
+    }
+
+    writeln!(out, "];")?;
+    writeln!(out)?;
+
+    writeln!(
+        out,
+        "/// Load the values of all metrics, including those of `{registry}`."
+    )?;
+    writeln!(out, "///")?;
+    writeln!(
+        out,
+        "/// Every metric is loaded on its own, so updates happening concurrently may only be"
+    )?;
+    writeln!(
+        out,
+        "/// partially visible. Use [`Self::snapshot_consistent`] to avoid that."
+    )?;
+    writeln!(out, "pub fn snapshot(&self) -> {snapshot} {{")?;
+    writeln!(out, "{snapshot} {{")?;
+
+    for Metric { name, kind, .. } in metrics {
+        match kind {
+            MetricKind::Counter
+                if options.labels.contains_key(name) || options.families.contains_key(name) =>
+            {
+                writeln!(out, "{name}: self.{name}.snapshot(),")?
+            }
+            MetricKind::Counter if builder.atomic_type != AtomicType::U64 => writeln!(
+                out,
+                "{name}: self.{name}.load(std::sync::atomic::Ordering::Relaxed) as u64,"
+            )?,
+            MetricKind::Counter | MetricKind::Gauge => writeln!(
+                out,
+                "{name}: self.{name}.load(std::sync::atomic::Ordering::Relaxed),"
+            )?,
+            MetricKind::Histogram => writeln!(out, "{name}: self.{name}.snapshot(),")?,
+        }
+    }
+
+    writeln!(out, "dynamic_metrics: {registry}.snapshot(),")?;
+    writeln!(out, "}}")?;
+    writeln!(out, "}}")?;
+    writeln!(out)?;
+
+    writeln!(
+        out,
+        "/// Load the values of all metrics, observing either all or none of the updates of"
+    )?;
+    writeln!(out, "/// every `consistent_update!` group.")?;
+    writeln!(out, "pub fn snapshot_consistent(&self) -> {snapshot} {{")?;
+    if metrics.is_empty() {
+        writeln!(out, "self.snapshot()")?;
+    } else {
+        writeln!(out, "self.__snapshot_lock.read(|| self.snapshot())")?;
+    }
+    writeln!(out, "}}")?;
+    writeln!(out)?;
+
+    writeln!(
+        out,
+        "/// Start a group of updates, see `consistent_update!`."
+    )?;
+    writeln!(
+        out,
+        "pub fn begin_consistent_update(&self) -> atomic_metrics_core::WriteGuard<'_> {{"
+    )?;
+    if metrics.is_empty() {
+        writeln!(out, "atomic_metrics_core::WriteGuard::noop()")?;
+    } else {
+        writeln!(out, "self.__snapshot_lock.write()")?;
+    }
+    writeln!(out, "}}")?;
+    writeln!(out)?;
+
+    writeln!(
+        out,
+        "/// Render all metrics in the Prometheus text exposition format 0.0.4."
+    )?;
+    writeln!(out, "pub fn render_prometheus(&self) -> String {{")?;
+    writeln!(out, "atomic_metrics_core::prometheus::render(self)")?;
+    writeln!(out, "}}")?;
+    writeln!(out)?;
+
+    writeln!(
+        out,
+        "/// Render all metrics with their exemplars in the OpenMetrics text format."
+    )?;
+    writeln!(out, "pub fn render_openmetrics(&self) -> String {{")?;
+    writeln!(out, "atomic_metrics_core::openmetrics::render(self)")?;
+    writeln!(out, "}}")?;
+    writeln!(out, "}}")?;
+    writeln!(out)?;
+
+    writeln!(out, "impl Default for {ty} {{")?;
+    writeln!(out, "fn default() -> Self {{")?;
+    writeln!(out, "Self::new()")?;
+    writeln!(out, "}}")?;
+    writeln!(out, "}}")?;
+    writeln!(out)?;
+
+    writeln!(out, "impl atomic_metrics_core::Collect for {ty} {{")?;
+    writeln!(
+        out,
+        "fn collect(&self, visitor: &mut dyn atomic_metrics_core::Visitor) {{"
+    )?;
+    writeln!(
+        out,
+        "atomic_metrics_core::Collect::collect(&self.snapshot(), visitor)"
+    )?;
+    writeln!(out, "}}")?;
+    if exemplars {
+        writeln!(out)?;
+        writeln!(
+            out,
+            "fn exemplars(&self) -> Vec<(&'static str, atomic_metrics_core::Exemplar)> {{"
+        )?;
+        writeln!(out, "[")?;
+        for Metric { name, .. } in metrics {
+            if options.keeps_exemplars(name) {
+                writeln!(out, "({name:?}, self.__exemplars.{name}.get()),")?;
+            }
+        }
+        writeln!(out, "]")?;
+        writeln!(out, ".into_iter()")?;
+        writeln!(
+            out,
+            ".filter_map(|(name, exemplar)| Some((name, exemplar?)))"
+        )?;
+        writeln!(out, ".collect()")?;
+        writeln!(out, "}}")?;
+    }
+    writeln!(out, "}}")?;
+    writeln!(out)?;
+
+    Ok(())
+}
+
+/// Write the `MetricId` enum with one variant per metric, named like the metric's field.
+///
+/// The enum is named after the recorder, see [`MetricsBuilder::type_name`].
+fn write_metric_id(
+    out: &mut impl Write,
+    builder: &MetricsBuilder,
+    metrics: &[Metric],
+) -> Result<()> {
+    let MetricsBuilder {
+        type_name: ty,
+        visibility: vis,
+        options,
+        ..
+    } = builder;
+    let id = builder.id_name();
+
+    writeln!(out, "/// Identifier of a metric of the `{ty}`.")?;
+    writeln!(out, "#[allow(non_camel_case_types, dead_code)]")?;
+    writeln!(
+        out,
+        "#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]"
+    )?;
+    writeln!(out, "{vis} enum {id} {{")?;
+
+    for Metric { name, .. } in metrics {
+        writeln!(out, "{name},")?;
+    }
+
+    writeln!(out, "}}")?;
+    writeln!(out)?;
+
+    writeln!(out, "#[allow(dead_code)]")?;
+    writeln!(out, "impl {id} {{")?;
+    writeln!(out, "/// All metrics, in the order of the fields.")?;
+    writeln!(out, "pub const ALL: &'static [{id}] = &[")?;
+
+    for Metric { name, .. } in metrics {
+        writeln!(out, "{id}::{name},")?;
+    }
+
+    writeln!(out, "];")?;
+    writeln!(out)?;
+
+    writeln!(out, "/// Name of the metric.")?;
+    writeln!(out, "pub const fn name(self) -> &'static str {{")?;
+    writeln!(out, "self.info().name")?;
+    writeln!(out, "}}")?;
+    writeln!(out)?;
+
+    writeln!(
+        out,
+        "/// Get the metric named `name`, `None` if there is no such metric."
+    )?;
+    writeln!(out, "pub fn from_name(name: &str) -> Option<{id}> {{")?;
+    if metrics.is_empty() {
+        writeln!(out, "let _ = name;")?;
+        writeln!(out, "None")?;
+    } else {
+        writeln!(out, "match name {{")?;
+        for Metric { name, .. } in metrics {
+            writeln!(out, "{name:?} => Some({id}::{name}),")?;
+        }
+        writeln!(out, "_ => None,")?;
+        writeln!(out, "}}")?;
+    }
+    writeln!(out, "}}")?;
+    writeln!(out)?;
+
+    writeln!(out, "/// Static description of the metric.")?;
+    writeln!(
+        out,
+        "pub const fn info(self) -> &'static atomic_metrics_core::MetricInfo {{"
+    )?;
+    writeln!(out, "match self {{")?;
+
+    for (idx, Metric { name, .. }) in metrics.iter().enumerate() {
+        writeln!(out, "{id}::{name} => &{ty}::METADATA[{idx}],")?;
+    }
+
+    writeln!(out, "}}")?;
+    writeln!(out, "}}")?;
+    writeln!(out, "}}")?;
+    writeln!(out)?;
+
+    writeln!(out, "#[allow(dead_code)]")?;
+    writeln!(out, "impl {ty} {{")?;
+    writeln!(out, "/// Borrow the value backing the metric `id`.")?;
+    writeln!(
+        out,
+        "pub fn get(&self, id: {id}) -> atomic_metrics_core::MetricRef<'_> {{"
+    )?;
+    writeln!(out, "match id {{")?;
+
+    for Metric { name, kind, .. } in metrics {
+        let variant = match kind {
+            MetricKind::Counter if options.hot.contains(name) => "HotCounter",
+            MetricKind::Counter if options.labels.contains_key(name) => "LabeledCounter",
+            MetricKind::Counter if options.families.contains_key(name) => "CounterFamily",
+            MetricKind::Counter if builder.atomic_type != AtomicType::U64 => "CustomCounter",
+            MetricKind::Counter => "Counter",
+            MetricKind::Gauge => "Gauge",
+            MetricKind::Histogram => "Histogram",
+        };
+        writeln!(
+            out,
+            "{id}::{name} => atomic_metrics_core::MetricRef::{variant}(&self.{name}),"
+        )?;
+    }
+
+    writeln!(out, "}}")?;
+    writeln!(out, "}}")?;
+    writeln!(out, "}}")?;
+    writeln!(out)?;
+
+    writeln!(out, "impl atomic_metrics_core::MetricLookup for {ty} {{")?;
+    writeln!(
+        out,
+        "fn get_by_name(&self, name: &str) -> Option<atomic_metrics_core::MetricRef<'_>> {{"
+    )?;
+    writeln!(out, "{id}::from_name(name).map(|id| self.get(id))")?;
+    writeln!(out, "}}")?;
+    writeln!(out, "}}")?;
+    writeln!(out)?;
+
+    Ok(())
+}
+
+/// Write the plain `MetricsSnapshot` struct holding the values of all metrics.
+fn write_snapshot(
+    out: &mut impl Write,
+    builder: &MetricsBuilder,
+    metrics: &[Metric],
+) -> Result<()> {
+    let MetricsBuilder {
+        type_name: ty,
+        visibility: vis,
+        options,
+        ..
+    } = builder;
+    let snapshot = builder.snapshot_name();
+
+    writeln!(
+        out,
+        "/// Values of all metrics of the `{ty}` at one point in time."
+    )?;
+    writeln!(out, "#[derive(Debug, Clone, PartialEq, Eq, Default)]")?;
+    writeln!(out, "{vis} struct {snapshot} {{")?;
+
+    for Metric { name, kind, .. } in metrics {
+        match kind {
+            MetricKind::Counter if options.labels.contains_key(name) => {
+                writeln!(out, "pub {name}: atomic_metrics_core::LabeledSnapshot,")?
+            }
+            MetricKind::Counter if options.families.contains_key(name) => {
+                writeln!(out, "pub {name}: atomic_metrics_core::FamilySnapshot,")?
+            }
+            MetricKind::Counter => writeln!(out, "pub {name}: u64,")?,
+            MetricKind::Gauge => writeln!(out, "pub {name}: i64,")?,
+            MetricKind::Histogram => {
+                writeln!(out, "pub {name}: atomic_metrics_core::HistogramSnapshot,")?
+            }
+        }
+    }
+
+    writeln!(
+        out,
+        "/// Metrics registered at runtime in `{}`.",
+        builder.registry_name()
+    )?;
+    writeln!(
+        out,
+        "pub dynamic_metrics: atomic_metrics_core::RegistrySnapshot,"
+    )?;
+    writeln!(out, "}}")?;
+    writeln!(out)?;
+
+    writeln!(out, "#[allow(dead_code)]")?;
+    writeln!(out, "impl {snapshot} {{")?;
+    writeln!(
+        out,
+        "/// Compute the increments since `previous`, see [`atomic_metrics_core::Delta`]."
+    )?;
+    writeln!(out, "pub fn delta(&self, previous: &Self) -> Self {{")?;
+    writeln!(out, "Self {{")?;
+
+    for Metric { name, kind, .. } in metrics {
+        match kind {
+            MetricKind::Counter | MetricKind::Histogram => writeln!(
+                out,
+                "{name}: atomic_metrics_core::Delta::delta(&self.{name}, &previous.{name}),"
+            )?,
+            MetricKind::Gauge => writeln!(out, "{name}: self.{name},")?,
+        }
+    }
+
+    writeln!(
+        out,
+        "dynamic_metrics: atomic_metrics_core::Delta::delta(&self.dynamic_metrics, &previous.dynamic_metrics),"
+    )?;
+    writeln!(out, "}}")?;
+    writeln!(out, "}}")?;
+    writeln!(out, "}}")?;
+    writeln!(out)?;
+
+    writeln!(out, "impl atomic_metrics_core::Delta for {snapshot} {{")?;
+    writeln!(out, "fn delta(&self, previous: &Self) -> Self {{")?;
+    writeln!(out, "{snapshot}::delta(self, previous)")?;
+    writeln!(out, "}}")?;
+    writeln!(out, "}}")?;
+    writeln!(out)?;
+
+    writeln!(out, "impl atomic_metrics_core::Collect for {snapshot} {{")?;
+    writeln!(
+        out,
+        "fn collect(&self, visitor: &mut dyn atomic_metrics_core::Visitor) {{"
+    )?;
+
+    for (idx, Metric { name, kind, .. }) in metrics.iter().enumerate() {
+        let info = format!("&{ty}::METADATA[{idx}]");
+        match kind {
+            MetricKind::Counter
+                if options.labels.contains_key(name) || options.families.contains_key(name) =>
+            {
+                writeln!(out, "self.{name}.visit({info}, visitor);")?
+            }
+            MetricKind::Counter => writeln!(out, "visitor.counter({info}, &[], self.{name});")?,
+            MetricKind::Gauge => writeln!(out, "visitor.gauge({info}, &[], self.{name});")?,
+            MetricKind::Histogram => {
+                writeln!(out, "visitor.histogram({info}, &[], &self.{name});")?
+            }
+        }
+    }
+
+    writeln!(
+        out,
+        "atomic_metrics_core::Collect::collect(&self.dynamic_metrics, visitor);"
+    )?;
+    writeln!(out, "}}")?;
+    writeln!(out, "}}")?;
+    writeln!(out)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn generate(builder: &MetricsBuilder) -> String {
+        let metrics = [
+            Metric::new("requests", MetricKind::Counter),
+            Metric::new("in_flight", MetricKind::Gauge),
+        ];
+        let mut out = Vec::new();
+        write_metrics(&mut out, builder, &metrics, false).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn names_items_after_the_recorder() {
+        let default = generate(&MetricsBuilder::new());
+        let jobs = generate(
+            &MetricsBuilder::new()
+                .type_name("JobsRecorder")
+                .static_name("JOBS"),
+        );
+
+        for (code, ty, snapshot, id, registry, static_name) in [
+            (
+                &default,
+                "MetricsRecorder",
+                "MetricsSnapshot",
+                "MetricId",
+                "DYNAMIC_METRICS",
+                "METRICS_RECORDER",
+            ),
+            (
+                &jobs,
+                "JobsRecorder",
+                "JobsSnapshot",
+                "JobsMetricId",
+                "JOBS_DYNAMIC_METRICS",
+                "JOBS",
+            ),
+        ] {
+            assert!(code.contains(&format!("pub struct {ty} {{")));
+            assert!(code.contains(&format!("pub struct {snapshot} {{")));
+            assert!(code.contains(&format!("pub enum {id} {{")));
+            assert!(code.contains(&format!("pub static {registry}:")));
+            assert!(code.contains(&format!("pub static {static_name}:")));
+            assert!(code.contains(&format!("dynamic_metrics: {registry}.snapshot(),")));
+            assert!(!code.contains("\nuse "));
+        }
+        assert!(!jobs.contains("MetricsRecorder"));
+        assert!(!jobs.contains(" MetricId"));
+        assert!(!jobs.contains(" DYNAMIC_METRICS"));
+    }
+
+    #[test]
+    fn keeps_exemplars_of_declared_counters_only() {
+        let code = generate(&MetricsBuilder::new().options(Options::new().exemplars("requests")));
+        assert!(code.contains(
+            "pub struct MetricsRecorderExemplars {\npub requests: atomic_metrics_core::ExemplarCell,\n}"
+        ));
+        assert!(code.contains("(\"requests\", self.__exemplars.requests.get()),"));
+        assert!(!code.contains("pub exemplars"));
+
+        let code = generate(&MetricsBuilder::new());
+        assert!(!code.contains("__exemplars"));
+        assert!(!code.contains("fn exemplars"));
+    }
+
+    #[test]
+    fn refuses_exemplars_of_other_metrics() {
+        let builder = MetricsBuilder::new().options(Options::new().exemplars("in_flight"));
+        let metrics = [Metric::new("in_flight", MetricKind::Gauge)];
+        let err = write_metrics(&mut Vec::new(), &builder, &metrics, false).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "`in_flight` keeps exemplars but is not a counter without labels"
+        );
+    }
+
+    #[test]
+    fn marks_set_counters_as_not_monotonic() {
+        let info = |name: &str, kind: &str, monotonic: bool| {
+            format!(
+                "name: {name:?},\nkind: atomic_metrics_core::MetricKind::{kind},\n\
+                 help: None,\nunit: None,\nmonotonic: {monotonic},"
+            )
+        };
+
+        let code = generate(&MetricsBuilder::new());
+        assert!(code.contains(&info("requests", "Counter", true)));
+        assert!(code.contains(&info("in_flight", "Gauge", false)));
+
+        let options = Options::new().non_monotonic("requests");
+        let code = generate(&MetricsBuilder::new().options(options));
+        assert!(code.contains(&info("requests", "Counter", false)));
+    }
+
+    #[test]
+    fn derives_names_from_the_type_name() {
+        let builder = MetricsBuilder::new().type_name("AppMetrics");
+        assert_eq!(builder.snapshot_name(), "AppMetricsSnapshot");
+        assert_eq!(builder.id_name(), "AppMetricId");
+        assert_eq!(builder.registry_name(), "APP_DYNAMIC_METRICS");
+
+        let builder = MetricsBuilder::new().type_name("HttpServerRecorder");
+        assert_eq!(builder.snapshot_name(), "HttpServerSnapshot");
+        assert_eq!(builder.id_name(), "HttpServerMetricId");
+        assert_eq!(builder.registry_name(), "HTTP_SERVER_DYNAMIC_METRICS");
+    }
+}
@@ -0,0 +1,191 @@
+//! Serialization of metrics in the plaintext protocol of Graphite's carbon.
+//!
+//! Every series is written as one `path value timestamp` line. Labels and global tags are
+//! attached in the tagged format of Graphite 1.1:
+//!
+//! ```text
+//! app.http_requests;host=a;method=get 2 1700000000
+//! ```
+//!
+//! Histograms are written as the series `<name>.count`, `<name>.sum` and one cumulative
+//! `<name>.bucket` series per bucket, tagged with its upper bound as `le`.
+
+use crate::{push::LineFormat, push_fmt, Collect, HistogramSnapshot, MetricInfo, Visitor};
+use std::{
+    fmt,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// Serializer for the Graphite plaintext protocol.
+#[derive(Debug, Clone, Default)]
+pub struct Plaintext {
+    prefix: Option<String>,
+    tags: Vec<(String, String)>,
+}
+
+impl Plaintext {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Prepend `prefix` and a dot to all metric paths.
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = Some(prefix.to_owned());
+        self
+    }
+
+    /// Attach the tag `key=value` to all series.
+    pub fn tag(mut self, key: &str, value: &str) -> Self {
+        self.tags.push((key.to_owned(), value.to_owned()));
+        self
+    }
+
+    /// Render all metrics of `metrics` with `timestamp` in second precision.
+    pub fn render(&self, metrics: &(impl Collect + ?Sized), timestamp: SystemTime) -> String {
+        let mut lines = Lines {
+            format: self,
+            timestamp: timestamp
+                .duration_since(UNIX_EPOCH)
+                .map(|since_epoch| since_epoch.as_secs())
+                .unwrap_or(0),
+            out: String::new(),
+        };
+        metrics.collect(&mut lines);
+        lines.out
+    }
+}
+
+impl LineFormat for Plaintext {
+    fn render(&self, metrics: &dyn Collect, timestamp: SystemTime) -> String {
+        Plaintext::render(self, metrics, timestamp)
+    }
+}
+
+struct Lines<'a> {
+    format: &'a Plaintext,
+    timestamp: u64,
+    out: String,
+}
+
+impl Lines<'_> {
+    fn line(
+        &mut self,
+        info: &MetricInfo,
+        suffix: &str,
+        labels: &[(&str, &str)],
+        value: impl fmt::Display,
+    ) {
+        if let Some(prefix) = &self.format.prefix {
+            self.out.push_str(&sanitize(prefix, &[';']));
+            self.out.push('.');
+        }
+        self.out.push_str(&sanitize(info.name, &[';']));
+        self.out.push_str(suffix);
+
+        let global_tags = self
+            .format
+            .tags
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()));
+        for (key, value) in global_tags.chain(labels.iter().copied()) {
+            // Graphite rejects a series with an empty tag value, such a label is left out.
+            if !value.is_empty() {
+                push_fmt!(
+                    self.out,
+                    ";{}={}",
+                    sanitize(key, &[';', '!', '^', '=', '~']),
+                    sanitize(value, &[';', '~'])
+                );
+            }
+        }
+
+        push_fmt!(self.out, " {value} {}\n", self.timestamp);
+    }
+}
+
+impl Visitor for Lines<'_> {
+    fn counter(&mut self, info: &MetricInfo, labels: &[(&str, &str)], value: u64) {
+        self.line(info, "", labels, value);
+    }
+
+    fn gauge(&mut self, info: &MetricInfo, labels: &[(&str, &str)], value: i64) {
+        self.line(info, "", labels, value);
+    }
+
+    fn histogram(
+        &mut self,
+        info: &MetricInfo,
+        labels: &[(&str, &str)],
+        histogram: &HistogramSnapshot,
+    ) {
+        self.line(info, ".count", labels, histogram.count);
+        self.line(info, ".sum", labels, histogram.sum);
+
+        for (bound, count) in histogram.cumulative() {
+            let le = match bound {
+                Some(bound) => bound.to_string(),
+                None => "inf".to_owned(),
+            };
+            let mut bucket_labels = labels.to_vec();
+            bucket_labels.push(("le", &le));
+            self.line(info, ".bucket", &bucket_labels, count);
+        }
+    }
+}
+
+/// Replace whitespace and the `reserved` characters of the format with `_`.
+fn sanitize(s: &str, reserved: &[char]) -> String {
+    s.chars()
+        .map(|c| {
+            if c.is_whitespace() || reserved.contains(&c) {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::{info, Metrics, Series},
+        MetricKind,
+    };
+    use std::time::Duration;
+
+    fn timestamp() -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+    }
+
+    #[test]
+    fn writes_tagged_series() {
+        let format = Plaintext::new().prefix("app").tag("host", "a");
+        assert_eq!(
+            format.render(&Metrics::new(3), timestamp()),
+            "app.requests;host=a;method=get 3 1700000000\n\
+             app.queued;host=a 1 1700000000\n\
+             app.in_flight;host=a -2 1700000000\n\
+             app.latency.count;host=a 3 1700000000\n\
+             app.latency.sum;host=a 40 1700000000\n\
+             app.latency.bucket;host=a;le=10 1 1700000000\n\
+             app.latency.bucket;host=a;le=inf 3 1700000000\n"
+        );
+    }
+
+    #[test]
+    fn sanitizes_paths_and_tags() {
+        let series = Series {
+            info: info("http requests", MetricKind::Counter),
+            labels: &[("method", "get;post"), ("path", ""), ("a=b", "c~d")],
+            value: 2,
+        };
+        assert_eq!(
+            Plaintext::new()
+                .tag("host", "a b")
+                .render(&series, timestamp()),
+            "http_requests;host=a_b;method=get_post;a_b=c_d 2 1700000000\n"
+        );
+    }
+}
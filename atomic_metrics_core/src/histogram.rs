@@ -0,0 +1,121 @@
+//! Lock-free histogram with fixed bucket bounds.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Bucket bounds used for histograms without declared bounds.
+pub const DEFAULT_BUCKETS: &[u64] = &[
+    1, 2, 5, 10, 25, 50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000,
+];
+
+/// Histogram counting observations into `N` buckets.
+///
+/// The first `N - 1` buckets have the inclusive upper bounds passed on creation, the last bucket
+/// catches all observations above the highest bound (`+Inf`).
+pub struct Histogram<const N: usize> {
+    bounds: &'static [u64],
+    buckets: [AtomicU64; N],
+    sum: AtomicU64,
+}
+
+impl<const N: usize> Histogram<N> {
+    /// Create a histogram with the ascending upper `bounds` of its buckets.
+    ///
+    /// Panics if `bounds` does not have exactly `N - 1` elements.
+    pub const fn new(bounds: &'static [u64]) -> Self {
+        assert!(
+            bounds.len() + 1 == N,
+            "histogram needs one bucket more than bounds"
+        );
+
+        Self {
+            bounds,
+            buckets: [const { AtomicU64::new(0) }; N],
+            sum: AtomicU64::new(0),
+        }
+    }
+
+    /// Record a single observation of `value`.
+    pub fn observe(&self, value: u64) {
+        let idx = self.bounds.partition_point(|&bound| bound < value);
+        self.buckets[idx].fetch_add(1, Ordering::Relaxed);
+        self.sum.fetch_add(value, Ordering::Relaxed);
+    }
+
+    /// Reset all buckets and the sum to zero.
+    pub fn reset(&self) {
+        for bucket in self.buckets.iter() {
+            bucket.store(0, Ordering::Relaxed);
+        }
+        self.sum.store(0, Ordering::Relaxed);
+    }
+
+    /// Load the current state of the histogram.
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        let buckets: Vec<_> = self
+            .buckets
+            .iter()
+            .map(|bucket| bucket.load(Ordering::Relaxed))
+            .collect();
+
+        HistogramSnapshot {
+            bounds: self.bounds,
+            count: buckets.iter().sum(),
+            buckets,
+            sum: self.sum.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Object-safe access to a [`Histogram`] regardless of its number of buckets.
+pub trait AnyHistogram: Sync {
+    /// Record a single observation of `value`.
+    fn observe(&self, value: u64);
+
+    /// Reset all buckets and the sum to zero.
+    fn reset(&self);
+
+    /// Load the current state of the histogram.
+    fn snapshot(&self) -> HistogramSnapshot;
+}
+
+impl<const N: usize> AnyHistogram for Histogram<N> {
+    fn observe(&self, value: u64) {
+        Histogram::observe(self, value)
+    }
+
+    fn reset(&self) {
+        Histogram::reset(self)
+    }
+
+    fn snapshot(&self) -> HistogramSnapshot {
+        Histogram::snapshot(self)
+    }
+}
+
+/// Values of a [`Histogram`] at one point in time.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct HistogramSnapshot {
+    /// Inclusive upper bounds of all buckets but the last.
+    pub bounds: &'static [u64],
+    /// Number of observations per bucket, the last one being the `+Inf` bucket.
+    pub buckets: Vec<u64>,
+    /// Sum of all observed values.
+    pub sum: u64,
+    /// Number of observations.
+    pub count: u64,
+}
+
+impl HistogramSnapshot {
+    /// Iterate over the upper bound and the cumulative count of each bucket.
+    ///
+    /// The upper bound of the last bucket is `None`, standing for `+Inf`.
+    pub fn cumulative(&self) -> impl Iterator<Item = (Option<u64>, u64)> + '_ {
+        let bounds = self.bounds.iter().copied().map(Some).chain([None]);
+        bounds
+            .zip(self.buckets.iter())
+            .scan(0, |total, (bound, count)| {
+                *total += count;
+                Some((bound, *total))
+            })
+    }
+}
@@ -0,0 +1,275 @@
+//! Minimal HTTP/1.1 server exposing metrics without further dependencies.
+//!
+//! The server answers `GET /metrics` in the Prometheus text format, or in the OpenMetrics text
+//! format if the client accepts it, and `GET /metrics.json` with a JSON object. Requests are
+//! handled one at a time on a single background thread, which is plenty for a scraper polling
+//! every few seconds.
+
+use crate::{json, openmetrics, prometheus, Collect};
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    thread,
+    time::Duration,
+};
+
+/// Time a client gets to send its request or to take the response before the connection is
+/// dropped.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Upper bound for the request head, anything longer is rejected.
+const MAX_REQUEST_HEAD: usize = 8 * 1024;
+
+/// Handle to a running metrics server.
+pub struct MetricsServer {
+    local_addr: SocketAddr,
+    shutdown: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl MetricsServer {
+    /// Address the server is listening on, useful when binding to port 0.
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Stop accepting connections and wait for the server thread to exit.
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        self.shutdown.store(true, Ordering::Relaxed);
+        // Wake up the blocking `accept` so the thread notices the flag.
+        let _ = TcpStream::connect(self.local_addr);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for MetricsServer {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Serve `metrics` on `addr` from a background thread.
+///
+/// The server runs until the returned [`MetricsServer`] is shut down or dropped.
+///
+/// ```ignore
+/// let server = atomic_metrics_core::http::serve("127.0.0.1:9100", &METRICS_RECORDER)?;
+/// ```
+pub fn serve<M>(addr: impl ToSocketAddrs, metrics: &'static M) -> io::Result<MetricsServer>
+where
+    M: Collect + Sync,
+{
+    let listener = TcpListener::bind(addr)?;
+    let local_addr = listener.local_addr()?;
+    let shutdown = Arc::new(AtomicBool::new(false));
+
+    let thread = {
+        let shutdown = shutdown.clone();
+        thread::Builder::new()
+            .name("metrics-http".into())
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    if shutdown.load(Ordering::Relaxed) {
+                        break;
+                    }
+                    if let Ok(stream) = stream {
+                        // A misbehaving client must not take down the server.
+                        let _ = handle_connection(stream, metrics);
+                    }
+                }
+            })?
+    };
+
+    Ok(MetricsServer {
+        local_addr,
+        shutdown,
+        thread: Some(thread),
+    })
+}
+
+fn handle_connection(stream: TcpStream, metrics: &impl Collect) -> io::Result<()> {
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    stream.set_write_timeout(Some(TIMEOUT))?;
+    let mut reader = BufReader::new(stream.try_clone()?);
+    // Limit the whole head, so a single endless line cannot exhaust memory either.
+    let mut head = reader.by_ref().take(MAX_REQUEST_HEAD as u64);
+
+    let mut request_line = String::new();
+    head.read_line(&mut request_line)?;
+
+    // Drain the headers, only checking which formats the client accepts.
+    let mut line = request_line.clone();
+    let mut accepts_openmetrics = false;
+    loop {
+        if !line.ends_with('\n') {
+            if head.limit() == 0 {
+                return respond(
+                    stream,
+                    "431 Request Header Fields Too Large",
+                    "text/plain",
+                    "",
+                );
+            }
+            // The client closed the connection before finishing the head.
+            break;
+        }
+
+        line.clear();
+        head.read_line(&mut line)?;
+        if line == "\r\n" || line == "\n" {
+            break;
+        }
+        if let Some((name, value)) = line.split_once(':') {
+            if name.trim().eq_ignore_ascii_case("accept")
+                && value.contains("application/openmetrics-text")
+            {
+                accepts_openmetrics = true;
+            }
+        }
+    }
+
+    let mut parts = request_line.split_whitespace();
+    let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+        return respond(stream, "400 Bad Request", "text/plain", "");
+    };
+    let path = target.split('?').next().unwrap_or(target);
+
+    match (method, path) {
+        ("GET", "/metrics") if accepts_openmetrics => respond(
+            stream,
+            "200 OK",
+            openmetrics::CONTENT_TYPE,
+            &openmetrics::render(metrics),
+        ),
+        ("GET", "/metrics") => respond(
+            stream,
+            "200 OK",
+            prometheus::CONTENT_TYPE,
+            &prometheus::render(metrics),
+        ),
+        ("GET", "/metrics.json") => {
+            respond(stream, "200 OK", json::CONTENT_TYPE, &json::render(metrics))
+        }
+        ("GET", _) => respond(stream, "404 Not Found", "text/plain", "not found\n"),
+        _ => respond(
+            stream,
+            "405 Method Not Allowed",
+            "text/plain",
+            "method not allowed\n",
+        ),
+    }
+}
+
+fn respond(mut stream: TcpStream, status: &str, content_type: &str, body: &str) -> io::Result<()> {
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\nContent-Type: {content_type}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::{info, Series},
+        MetricInfo, MetricKind,
+    };
+
+    static REQUESTS: Series = Series {
+        info: MetricInfo {
+            help: Some("Handled requests."),
+            ..info("requests", MetricKind::Counter)
+        },
+        labels: &[],
+        value: 3,
+    };
+
+    /// Send `request` to a fresh server and return the whole response.
+    fn exchange(request: &[u8]) -> String {
+        let server = serve("127.0.0.1:0", &REQUESTS).unwrap();
+        let mut stream = TcpStream::connect(server.local_addr()).unwrap();
+        stream.write_all(request).unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        server.shutdown();
+        response
+    }
+
+    #[test]
+    fn serves_prometheus_text() {
+        let body = "# HELP requests Handled requests.\n# TYPE requests counter\nrequests 3\n";
+        assert_eq!(
+            exchange(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n"),
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                prometheus::CONTENT_TYPE,
+                body.len()
+            )
+        );
+    }
+
+    #[test]
+    fn serves_openmetrics_when_accepted() {
+        let body =
+            "# TYPE requests counter\n# HELP requests Handled requests.\nrequests_total 3\n# EOF\n";
+        assert_eq!(
+            exchange(
+                b"GET /metrics?x=1 HTTP/1.1\r\naccept: application/openmetrics-text;version=1.0.0,text/plain;q=0.5\r\n\r\n"
+            ),
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+                openmetrics::CONTENT_TYPE,
+                body.len()
+            )
+        );
+    }
+
+    #[test]
+    fn serves_json() {
+        assert_eq!(
+            exchange(b"GET /metrics.json HTTP/1.1\r\n\r\n"),
+            format!(
+                "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: 14\r\nConnection: close\r\n\r\n{{\"requests\":3}}",
+                json::CONTENT_TYPE
+            )
+        );
+    }
+
+    #[test]
+    fn rejects_other_paths_and_methods() {
+        assert_eq!(
+            exchange(b"GET /other HTTP/1.1\r\n\r\n"),
+            "HTTP/1.1 404 Not Found\r\nContent-Type: text/plain\r\nContent-Length: 10\r\nConnection: close\r\n\r\nnot found\n"
+        );
+        assert_eq!(
+            exchange(b"POST /metrics HTTP/1.1\r\n\r\n"),
+            "HTTP/1.1 405 Method Not Allowed\r\nContent-Type: text/plain\r\nContent-Length: 19\r\nConnection: close\r\n\r\nmethod not allowed\n"
+        );
+        assert_eq!(
+            exchange(b"\r\n\r\n"),
+            "HTTP/1.1 400 Bad Request\r\nContent-Type: text/plain\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn rejects_oversized_head() {
+        let mut request = b"GET /metrics HTTP/1.1\r\nX-Padding: ".to_vec();
+        request.resize(MAX_REQUEST_HEAD, b'a');
+        assert_eq!(
+            exchange(&request),
+            "HTTP/1.1 431 Request Header Fields Too Large\r\nContent-Type: text/plain\r\nContent-Length: 0\r\nConnection: close\r\n\r\n"
+        );
+    }
+}
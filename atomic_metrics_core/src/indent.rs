@@ -0,0 +1,93 @@
+//! Writer indenting generated code, so it is readable without running `rustfmt`.
+
+use std::io::{self, Write};
+
+/// Width of one level of indentation.
+const INDENT: &str = "    ";
+
+/// Writer indenting every line by the number of brackets opened in the previous lines.
+///
+/// The generated code is written line by line without indentation. Brackets in string literals
+/// and comments are ignored.
+pub(crate) struct Indented<W: Write> {
+    inner: W,
+    depth: usize,
+    line: Vec<u8>,
+}
+
+impl<W: Write> Indented<W> {
+    pub(crate) fn new(inner: W) -> Self {
+        Self {
+            inner,
+            depth: 0,
+            line: Vec::new(),
+        }
+    }
+
+    fn write_line(&mut self) -> io::Result<()> {
+        let line = String::from_utf8_lossy(&self.line);
+        let line = line.trim();
+
+        if line.is_empty() {
+            writeln!(self.inner)?;
+        } else {
+            let (leading_closes, balance) = count_brackets(line);
+            let depth = self.depth.saturating_sub(leading_closes);
+            writeln!(self.inner, "{}{line}", INDENT.repeat(depth))?;
+            self.depth = self.depth.saturating_add_signed(balance);
+        }
+
+        self.line.clear();
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for Indented<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for &byte in buf {
+            if byte == b'\n' {
+                self.write_line()?;
+            } else {
+                self.line.push(byte);
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.line.is_empty() {
+            self.write_line()?;
+        }
+        self.inner.flush()
+    }
+}
+
+/// Count the closing brackets at the start of `line` and the balance of opened minus closed
+/// brackets in the whole line.
+fn count_brackets(line: &str) -> (usize, isize) {
+    if line.starts_with("//") {
+        return (0, 0);
+    }
+
+    let leading_closes = line
+        .chars()
+        .take_while(|c| matches!(c, '}' | ']' | ')'))
+        .count();
+
+    let mut balance = 0;
+    let mut in_string = false;
+    let mut escaped = false;
+    for c in line.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if in_string => escaped = true,
+            '"' => in_string = !in_string,
+            _ if in_string => {}
+            '{' | '[' | '(' => balance += 1,
+            '}' | ']' | ')' => balance -= 1,
+            _ => {}
+        }
+    }
+
+    (leading_closes, balance)
+}
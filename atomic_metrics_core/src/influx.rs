@@ -0,0 +1,226 @@
+//! Serialization of metrics in the InfluxDB line protocol.
+//!
+//! By default every metric is written as its own measurement with a single `value` field:
+//!
+//! ```text
+//! http_requests,host=a,method=get value=2i 1700000000000000000
+//! ```
+//!
+//! With a configured measurement, all metrics are written as fields of that measurement instead:
+//!
+//! ```text
+//! app,host=a,method=get http_requests=2i 1700000000000000000
+//! ```
+//!
+//! Histograms are written with the fields `count`, `sum` and one cumulative `bucket_<le>` field
+//! per bucket, prefixed with the metric name and an underscore if a measurement is configured.
+//!
+//! All values are signed integer fields, since unsigned ones are not supported by InfluxDB 1.x.
+//! Counters beyond `i64::MAX` are written as `i64::MAX`.
+
+use crate::{push::LineFormat, push_fmt, Collect, HistogramSnapshot, MetricInfo, Visitor};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Serializer for the InfluxDB line protocol.
+#[derive(Debug, Clone, Default)]
+pub struct LineProtocol {
+    measurement: Option<String>,
+    tags: Vec<(String, String)>,
+}
+
+impl LineProtocol {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Write all metrics as fields of the measurement `measurement`.
+    pub fn measurement(mut self, measurement: &str) -> Self {
+        self.measurement = Some(measurement.to_owned());
+        self
+    }
+
+    /// Attach the tag `key=value` to all lines.
+    pub fn tag(mut self, key: &str, value: &str) -> Self {
+        self.tags.push((key.to_owned(), value.to_owned()));
+        self
+    }
+
+    /// Render all metrics of `metrics` with `timestamp` in nanosecond precision.
+    pub fn render(&self, metrics: &(impl Collect + ?Sized), timestamp: SystemTime) -> String {
+        let mut lines = Lines {
+            format: self,
+            timestamp: timestamp
+                .duration_since(UNIX_EPOCH)
+                .map(|since_epoch| since_epoch.as_nanos())
+                .unwrap_or(0),
+            out: String::new(),
+        };
+        metrics.collect(&mut lines);
+        lines.out
+    }
+}
+
+impl LineFormat for LineProtocol {
+    fn render(&self, metrics: &dyn Collect, timestamp: SystemTime) -> String {
+        LineProtocol::render(self, metrics, timestamp)
+    }
+}
+
+struct Lines<'a> {
+    format: &'a LineProtocol,
+    timestamp: u128,
+    out: String,
+}
+
+impl Lines<'_> {
+    /// Write one line with the `fields` of the metric `info`.
+    ///
+    /// With a configured measurement, the field names are prefixed with the metric name and the
+    /// field `value` is named like the metric.
+    fn line(&mut self, info: &MetricInfo, labels: &[(&str, &str)], fields: &[(String, String)]) {
+        let (measurement, prefix) = match &self.format.measurement {
+            Some(measurement) => (measurement.as_str(), Some(info.name)),
+            None => (info.name, None),
+        };
+
+        self.out.push_str(&escape(measurement, &[',', ' ']));
+        let global_tags = self
+            .format
+            .tags
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()));
+        for (key, value) in global_tags.chain(labels.iter().copied()) {
+            // The line protocol cannot express an empty tag value, such a label is left out.
+            if !value.is_empty() {
+                push_fmt!(
+                    self.out,
+                    ",{}={}",
+                    escape(key, &[',', '=', ' ']),
+                    escape(value, &[',', '=', ' '])
+                );
+            }
+        }
+
+        for (idx, (field, value)) in fields.iter().enumerate() {
+            let field = match (prefix, field.as_str()) {
+                (Some(prefix), "value") => prefix.to_owned(),
+                (Some(prefix), field) => format!("{prefix}_{field}"),
+                (None, field) => field.to_owned(),
+            };
+            let separator = if idx == 0 { ' ' } else { ',' };
+            push_fmt!(
+                self.out,
+                "{separator}{}={value}",
+                escape(&field, &[',', '=', ' '])
+            );
+        }
+
+        push_fmt!(self.out, " {}\n", self.timestamp);
+    }
+}
+
+impl Visitor for Lines<'_> {
+    fn counter(&mut self, info: &MetricInfo, labels: &[(&str, &str)], value: u64) {
+        self.line(info, labels, &[("value".to_owned(), integer(value))]);
+    }
+
+    fn gauge(&mut self, info: &MetricInfo, labels: &[(&str, &str)], value: i64) {
+        self.line(info, labels, &[("value".to_owned(), format!("{value}i"))]);
+    }
+
+    fn histogram(
+        &mut self,
+        info: &MetricInfo,
+        labels: &[(&str, &str)],
+        histogram: &HistogramSnapshot,
+    ) {
+        let mut fields = vec![
+            ("count".to_owned(), integer(histogram.count)),
+            ("sum".to_owned(), integer(histogram.sum)),
+        ];
+        for (bound, count) in histogram.cumulative() {
+            let field = match bound {
+                Some(bound) => format!("bucket_{bound}"),
+                None => "bucket_inf".to_owned(),
+            };
+            fields.push((field, integer(count)));
+        }
+        self.line(info, labels, &fields);
+    }
+}
+
+/// Format `value` as a signed integer field, saturating at `i64::MAX`.
+fn integer(value: u64) -> String {
+    format!("{}i", i64::try_from(value).unwrap_or(i64::MAX))
+}
+
+/// Escape backslashes and the `special` characters with a backslash.
+fn escape(s: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '\n' => escaped.push_str("\\n"),
+            c if c == '\\' || special.contains(&c) => {
+                escaped.push('\\');
+                escaped.push(c);
+            }
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::{info, Metrics, Series},
+        MetricKind,
+    };
+    use std::time::Duration;
+
+    fn timestamp() -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(1_700_000_000)
+    }
+
+    #[test]
+    fn writes_measurement_per_metric() {
+        let format = LineProtocol::new().tag("host", "a");
+        assert_eq!(
+            format.render(&Metrics::new(3), timestamp()),
+            "requests,host=a,method=get value=3i 1700000000000000000\n\
+             queued,host=a value=1i 1700000000000000000\n\
+             in_flight,host=a value=-2i 1700000000000000000\n\
+             latency,host=a count=3i,sum=40i,bucket_10=1i,bucket_inf=3i 1700000000000000000\n"
+        );
+    }
+
+    #[test]
+    fn writes_fields_of_one_measurement() {
+        let format = LineProtocol::new().measurement("app");
+        assert_eq!(
+            format.render(&Metrics::new(3), timestamp()),
+            "app,method=get requests=3i 1700000000000000000\n\
+             app queued=1i 1700000000000000000\n\
+             app in_flight=-2i 1700000000000000000\n\
+             app latency_count=3i,latency_sum=40i,latency_bucket_10=1i,latency_bucket_inf=3i \
+             1700000000000000000\n"
+        );
+    }
+
+    #[test]
+    fn escapes_tags_and_saturates_integers() {
+        let series = Series {
+            info: info("http requests", MetricKind::Counter),
+            labels: &[("method", "get,post"), ("path", ""), ("a=b", "c\\d")],
+            value: u64::MAX,
+        };
+        assert_eq!(
+            LineProtocol::new()
+                .tag("host", "a b")
+                .render(&series, timestamp()),
+            "http\\ requests,host=a\\ b,method=get\\,post,a\\=b=c\\\\d \
+             value=9223372036854775807i 1700000000000000000\n"
+        );
+    }
+}
@@ -0,0 +1,84 @@
+//! Rendering of metrics as a flat JSON object mapping metric names to values.
+//!
+//! Series of labeled metrics are keyed by the name followed by their labels in Prometheus
+//! notation, e.g. `http_requests{method="get"}`.
+
+use crate::{prometheus::format_labels, push_fmt, Collect, HistogramSnapshot, MetricInfo, Visitor};
+
+/// Content type of the JSON object returned by [`render`].
+pub const CONTENT_TYPE: &str = "application/json";
+
+/// Render all metrics of `metrics` as a JSON object.
+pub fn render(metrics: &impl Collect) -> String {
+    let mut renderer = Renderer {
+        out: String::from("{"),
+    };
+    metrics.collect(&mut renderer);
+    renderer.out.push('}');
+    renderer.out
+}
+
+struct Renderer {
+    out: String,
+}
+
+impl Renderer {
+    fn key(&mut self, name: &str, labels: &[(&str, &str)]) {
+        if self.out.len() > 1 {
+            self.out.push(',');
+        }
+        write_string(
+            &mut self.out,
+            &format!("{name}{}", format_labels(labels, None)),
+        );
+        self.out.push(':');
+    }
+}
+
+impl Visitor for Renderer {
+    fn counter(&mut self, info: &MetricInfo, labels: &[(&str, &str)], value: u64) {
+        self.key(info.name, labels);
+        push_fmt!(self.out, "{value}");
+    }
+
+    fn gauge(&mut self, info: &MetricInfo, labels: &[(&str, &str)], value: i64) {
+        self.key(info.name, labels);
+        push_fmt!(self.out, "{value}");
+    }
+
+    fn histogram(
+        &mut self,
+        info: &MetricInfo,
+        labels: &[(&str, &str)],
+        histogram: &HistogramSnapshot,
+    ) {
+        self.key(info.name, labels);
+        push_fmt!(
+            self.out,
+            "{{\"bounds\":{:?},\"buckets\":{:?},\"sum\":{},\"count\":{}}}",
+            histogram.bounds,
+            histogram.buckets,
+            histogram.sum,
+            histogram.count
+        );
+    }
+}
+
+/// Write `s` as a quoted and escaped JSON string.
+pub(crate) fn write_string(out: &mut String, s: &str) {
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if c.is_control() => {
+                push_fmt!(out, "\\u{:04x}", c as u32);
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+}
@@ -0,0 +1,283 @@
+//! Counters with labels from a fixed set of values, backed by a flat array of atomics.
+
+use crate::{Delta, MetricInfo, Visitor};
+use std::{
+    marker::PhantomData,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// Label of a [`LabeledCounter`], implemented by the generated fieldless label enums.
+pub trait LabelValue: Copy {
+    /// Name of the label, e.g. `method`.
+    const KEY: &'static str;
+    /// All values of the label, in the order of [`LabelValue::index`].
+    const VALUES: &'static [&'static str];
+
+    /// Position of this value in [`LabelValue::VALUES`].
+    fn index(self) -> usize;
+}
+
+/// Name and possible values of one label dimension.
+pub type LabelDimension = (&'static str, &'static [&'static str]);
+
+/// Tuple of [`LabelValue`]s identifying one series of a [`LabeledCounter`].
+pub trait LabelSet: Copy {
+    /// Name and values of every label, in the order of the tuple.
+    const DIMENSIONS: &'static [LabelDimension];
+    /// Number of distinct label combinations.
+    const CARDINALITY: usize;
+
+    /// Position of this combination in row-major order.
+    fn index(self) -> usize;
+}
+
+macro_rules! impl_label_set {
+    ($($label:ident),+) => {
+        impl<$($label: LabelValue),+> LabelSet for ($($label,)+) {
+            const DIMENSIONS: &'static [LabelDimension] = &[$(($label::KEY, $label::VALUES)),+];
+            const CARDINALITY: usize = 1 $(* $label::VALUES.len())+;
+
+            #[allow(non_snake_case)]
+            fn index(self) -> usize {
+                let ($($label,)+) = self;
+                let mut index = 0;
+                $(index = index * $label::VALUES.len() + $label.index();)+
+                index
+            }
+        }
+    };
+}
+
+impl_label_set!(A);
+impl_label_set!(A, B);
+impl_label_set!(A, B, C);
+impl_label_set!(A, B, C, D);
+
+/// Counter with one `AtomicU64` for each of the `N` combinations of the labels `L`.
+///
+/// Selecting a series is plain index arithmetic, without hashing or allocation.
+pub struct LabeledCounter<L, const N: usize> {
+    counters: [AtomicU64; N],
+    labels: PhantomData<fn(L)>,
+}
+
+impl<L: LabelSet, const N: usize> LabeledCounter<L, N> {
+    /// Create a counter with all series at zero.
+    ///
+    /// Panics if `N` is not the number of label combinations.
+    pub const fn new() -> Self {
+        assert!(L::CARDINALITY == N, "wrong number of label combinations");
+
+        Self {
+            counters: [const { AtomicU64::new(0) }; N],
+            labels: PhantomData,
+        }
+    }
+
+    /// Borrow the atomic value of the series with `labels`.
+    pub fn with(&self, labels: L) -> &AtomicU64 {
+        &self.counters[labels.index()]
+    }
+
+    /// Borrow the atomic value of the series with the `(key, value)` pairs `labels`, given as
+    /// strings in any order.
+    ///
+    /// Returns `None` unless `labels` has exactly one known value for every label.
+    pub fn find(&self, labels: &[(&str, &str)]) -> Option<&AtomicU64> {
+        if labels.len() != L::DIMENSIONS.len() {
+            return None;
+        }
+
+        let mut index = 0;
+        for (key, values) in L::DIMENSIONS {
+            let (_, value) = labels.iter().find(|(k, _)| k == key)?;
+            let position = values.iter().position(|v| v == value)?;
+            index = index * values.len() + position;
+        }
+        Some(&self.counters[index])
+    }
+
+    /// Load the values of all series.
+    pub fn snapshot(&self) -> LabeledSnapshot {
+        LabeledSnapshot {
+            dimensions: L::DIMENSIONS,
+            values: self
+                .counters
+                .iter()
+                .map(|counter| counter.load(Ordering::Relaxed))
+                .collect(),
+        }
+    }
+}
+
+impl<L: LabelSet, const N: usize> Default for LabeledCounter<L, N> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Object-safe access to a [`LabeledCounter`] regardless of its labels.
+pub trait AnyLabeledCounter: Sync {
+    /// Borrow the atomic value of the series with the given labels, see [`LabeledCounter::find`].
+    fn find(&self, labels: &[(&str, &str)]) -> Option<&AtomicU64>;
+
+    /// Load the values of all series.
+    fn snapshot(&self) -> LabeledSnapshot;
+}
+
+impl<L: LabelSet, const N: usize> AnyLabeledCounter for LabeledCounter<L, N> {
+    fn find(&self, labels: &[(&str, &str)]) -> Option<&AtomicU64> {
+        LabeledCounter::find(self, labels)
+    }
+
+    fn snapshot(&self) -> LabeledSnapshot {
+        LabeledCounter::snapshot(self)
+    }
+}
+
+/// Values of all series of a [`LabeledCounter`] at one point in time.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct LabeledSnapshot {
+    /// Name and values of every label.
+    pub dimensions: &'static [LabelDimension],
+    /// Value of every label combination in row-major order.
+    pub values: Vec<u64>,
+}
+
+impl LabeledSnapshot {
+    /// Iterate over the labels and the value of every series.
+    pub fn series(&self) -> impl Iterator<Item = (Vec<(&'static str, &'static str)>, u64)> + '_ {
+        self.values.iter().enumerate().map(|(mut index, &value)| {
+            let mut labels = vec![("", ""); self.dimensions.len()];
+            for (label, (key, values)) in labels.iter_mut().zip(self.dimensions).rev() {
+                *label = (*key, values[index % values.len()]);
+                index /= values.len();
+            }
+            (labels, value)
+        })
+    }
+
+    /// Pass every series as a counter described by `info` to `visitor`.
+    pub fn visit(&self, info: &MetricInfo, visitor: &mut dyn Visitor) {
+        for (labels, value) in self.series() {
+            visitor.counter(info, &labels, value);
+        }
+    }
+}
+
+impl Delta for LabeledSnapshot {
+    fn delta(&self, previous: &Self) -> Self {
+        if self.values.len() != previous.values.len() {
+            return self.clone();
+        }
+
+        LabeledSnapshot {
+            dimensions: self.dimensions,
+            values: self
+                .values
+                .iter()
+                .zip(previous.values.iter())
+                .map(|(current, previous)| current.delta(previous))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ptr;
+
+    #[derive(Clone, Copy)]
+    enum Method {
+        Get,
+        Post,
+    }
+
+    impl LabelValue for Method {
+        const KEY: &'static str = "method";
+        const VALUES: &'static [&'static str] = &["get", "post"];
+
+        fn index(self) -> usize {
+            self as usize
+        }
+    }
+
+    #[derive(Clone, Copy)]
+    enum Status {
+        Ok,
+        NotFound,
+        Error,
+    }
+
+    impl LabelValue for Status {
+        const KEY: &'static str = "status";
+        const VALUES: &'static [&'static str] = &["ok", "not_found", "error"];
+
+        fn index(self) -> usize {
+            self as usize
+        }
+    }
+
+    const COMBINATIONS: [(Method, Status); 6] = [
+        (Method::Get, Status::Ok),
+        (Method::Get, Status::NotFound),
+        (Method::Get, Status::Error),
+        (Method::Post, Status::Ok),
+        (Method::Post, Status::NotFound),
+        (Method::Post, Status::Error),
+    ];
+
+    #[test]
+    fn finds_and_lists_every_series_by_its_labels() {
+        let counter = LabeledCounter::<(Method, Status), 6>::new();
+        for (idx, labels) in COMBINATIONS.into_iter().enumerate() {
+            counter
+                .with(labels)
+                .fetch_add(idx as u64 + 1, Ordering::Relaxed);
+        }
+
+        let series: Vec<_> = counter.snapshot().series().collect();
+        assert_eq!(series.len(), COMBINATIONS.len());
+        for (idx, (labels, (pairs, value))) in COMBINATIONS.into_iter().zip(series).enumerate() {
+            let (method, status) = labels;
+            let expected = [
+                ("method", Method::VALUES[method.index()]),
+                ("status", Status::VALUES[status.index()]),
+            ];
+            assert_eq!(pairs, expected);
+            assert_eq!(value, idx as u64 + 1);
+
+            // The order of the pairs does not matter.
+            let reversed = [expected[1], expected[0]];
+            assert!(ptr::eq(
+                counter.find(&expected).unwrap(),
+                counter.with(labels)
+            ));
+            assert!(ptr::eq(
+                counter.find(&reversed).unwrap(),
+                counter.with(labels)
+            ));
+        }
+    }
+
+    #[test]
+    fn finds_no_series_without_exactly_one_known_value_per_label() {
+        let counter = LabeledCounter::<(Method, Status), 6>::new();
+        assert!(counter
+            .find(&[("method", "get"), ("status", "ok")])
+            .is_some());
+
+        assert!(counter.find(&[("method", "get")]).is_none());
+        assert!(counter
+            .find(&[("method", "get"), ("method", "post")])
+            .is_none());
+        assert!(counter.find(&[("method", "get"), ("code", "ok")]).is_none());
+        assert!(counter
+            .find(&[("method", "put"), ("status", "ok")])
+            .is_none());
+        assert!(counter
+            .find(&[("method", "get"), ("status", "ok"), ("host", "a")])
+            .is_none());
+    }
+}
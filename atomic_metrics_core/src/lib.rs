@@ -1,168 +1,301 @@
-use anyhow::{bail, Result};
-use glob::glob;
-use regex::Regex;
-use std::{
-    collections::HashSet,
-    env, fs,
-    io::{self, Write},
-    path::Path,
-    process,
+mod builder;
+mod discover;
+mod exemplar;
+#[cfg(feature = "metrics")]
+pub mod facade;
+mod family;
+mod generate;
+pub mod graphite;
+mod histogram;
+#[cfg(feature = "http")]
+pub mod http;
+mod indent;
+pub mod influx;
+pub mod json;
+mod labels;
+mod macros;
+mod manifest;
+pub mod openmetrics;
+#[cfg(feature = "otlp")]
+pub mod otlp;
+pub mod prometheus;
+mod push;
+mod rate;
+mod registry;
+mod sharded;
+mod snapshot_lock;
+pub mod statsd;
+mod workspace;
+
+pub use builder::{AtomicType, MetricsBuilder};
+pub use discover::{find_metric_usages, MetricUsage};
+pub use exemplar::{Exemplar, ExemplarCell, MAX_TRACE_ID_LEN};
+pub use family::{CounterFamily, FamilyLabels, FamilySnapshot, OVERFLOW_LABEL};
+pub use generate::{
+    generate_metrics_recorder, generate_metrics_recorder_with_metrics,
+    generate_metrics_recorder_with_names, generate_metrics_recorder_with_options, Metric,
+    MetricKind, Options,
+};
+pub use histogram::{AnyHistogram, Histogram, HistogramSnapshot, DEFAULT_BUCKETS};
+pub use labels::{
+    AnyLabeledCounter, LabelDimension, LabelSet, LabelValue, LabeledCounter, LabeledSnapshot,
 };
+#[cfg(atomic_metrics_disabled)]
+#[doc(hidden)]
+pub use macros::__DISABLED_COUNTER;
+pub use manifest::generate_metrics_recorder_from_manifest;
+pub use push::{push_tcp, LineFormat, PushHandle};
+pub use rate::{Delta, Rate, RateTracker};
+pub use registry::{Registry, RegistrySnapshot};
+pub use sharded::{ShardedCounter, SHARDS};
+pub use snapshot_lock::{SnapshotLock, WriteGuard};
+
+use std::sync::{
+    atomic::{AtomicI64, AtomicU32, AtomicU64, AtomicUsize, Ordering},
+    Mutex, MutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard,
+};
+
+/// A set of metrics which can be walked by exporters.
+///
+/// Implemented by the generated `MetricsRecorder`.
+pub trait Collect {
+    /// Pass every metric with its current value to `visitor`.
+    fn collect(&self, visitor: &mut dyn Visitor);
+
+    /// Latest exemplars of the counters without labels, by metric name.
+    ///
+    /// Only read by exporters supporting exemplars, like [`openmetrics`]. Empty by default.
+    fn exemplars(&self) -> Vec<(&'static str, Exemplar)> {
+        Vec::new()
+    }
+}
 
-/// Get the counter `name` as borrow of the atomic value.
-#[macro_export]
-macro_rules! get_counter {
-    ($name:ident) => {
-        &METRICS_RECORDER.$name
-    };
-}
-
-/// Increment the counter `name` by `value`.
-#[macro_export]
-macro_rules! increment_metric {
-    ($name:ident, $value:expr) => {
-        METRICS_RECORDER
-            .$name
-            .fetch_add($value, std::sync::atomic::Ordering::Relaxed)
-    };
-}
-
-/// Increment the counter `name` by one.
-#[macro_export]
-macro_rules! tick_metric {
-    ($name:ident) => {
-        METRICS_RECORDER
-            .$name
-            .fetch_add(1, std::sync::atomic::Ordering::Relaxed)
-    };
-}
-
-/// Set the counter `name` to `value`.
-#[macro_export]
-macro_rules! set_metric {
-    ($name:ident, $value:expr) => {
-        METRICS_RECORDER
-            .$name
-            .store($value, std::sync::atomic::Ordering::Relaxed)
-    };
-}
-
-/// Reset the counter `name` to zero.
-#[macro_export]
-macro_rules! reset_metric {
-    ($name:ident) => {
-        METRICS_RECORDER
-            .$name
-            .store(0, std::sync::atomic::Ordering::Relaxed)
-    };
-}
-
-/// Load the value of the counter `name`.
-#[macro_export]
-macro_rules! load_metric {
-    ($name:ident) => {
-        METRICS_RECORDER
-            .$name
-            .load(std::sync::atomic::Ordering::Relaxed)
-    };
-}
-
-/// Generate the global `MetricsRecorder` based on all metrics usages in the source directory.
-pub fn generate_metrics_recorder() -> Result<()> {
-    println!("cargo:rerun-if-changed=src/");
-    let metric_names = get_metric_names("src/**/*.rs")?;
-
-    generate_metrics_recorder_with_names(metric_names.iter().map(|x| x.as_str()))
-}
-
-/// Generate the global `MetricsRecorder` with all the metrics names passed.
+/// Receiver of metric values during [`Collect::collect`].
 ///
-/// There will be a compilation error if you try to access/modify a metric not mentioned here.
-pub fn generate_metrics_recorder_with_names<'a>(
-    metric_names: impl Iterator<Item = &'a str> + Clone,
-) -> Result<()> {
-    let output = Path::new(&env::var("OUT_DIR")?).join("metrics.rs");
-    let mut out = io::BufWriter::new(fs::File::create(&output)?);
-
-    writeln!(out, "use std::sync::atomic::AtomicU64;")?;
-    writeln!(out)?;
-    writeln!(out, "pub struct MetricsRecorder {{")?;
-
-    for metric in metric_names.clone() {
-        writeln!(out, "pub {metric}: AtomicU64,")?;
-    }
-
-    writeln!(out, "}}")?;
-    writeln!(out)?;
-
-    writeln!(out, "impl MetricsRecorder {{")?;
-    writeln!(out, "pub const fn new() -> Self {{")?;
-    writeln!(out, "Self {{")?;
-
-    for metric in metric_names {
-        writeln!(out, "{metric}: AtomicU64::new(0),")?;
-    }
-
-    writeln!(out, "}}")?;
-    writeln!(out, "}}")?;
-    writeln!(out, "}}")?;
-    writeln!(out)?;
-
-    writeln!(
-        out,
-        "pub static METRICS_RECORDER: MetricsRecorder = MetricsRecorder::new();"
-    )?;
-
-    drop(out);
-
-    let output = process::Command::new("rustfmt").arg(&output).output()?;
-    if !output.status.success() {
-        bail!(
-            "failed to format generated code:\n{}\n{}",
-            String::from_utf8_lossy(&output.stdout),
-            String::from_utf8_lossy(&output.stderr)
-        );
-    }
-
-    Ok(())
-}
-
-static GET_COUNTER_REGEX: &str = r"get_counter!\([\n]?[\s]*([\d\w]+)[)\n,]";
-static INCREMENT_METRIC_REGEX: &str = r"increment_metric!\([\n]?[\s]*([\d\w]+)[)\n,]";
-static TICK_METRIC_REGEX: &str = r"tick_metric!\([\n]?[\s]*([\d\w]+)[)\n,]";
-static SET_METRIC_REGEX: &str = r"set_metric!\([\n]?[\s]*([\d\w]+)[)\n,]";
-static RESET_METRIC_REGEX: &str = r"reset_metric!\([\n]?[\s]*([\d\w]+)[)\n,]";
-static LOAD_METRIC_REGEX: &str = r"load_metric!\([\n]?[\s]*([\d\w]+)[)\n,]";
-
-/// Extract metric names by sifting through the files in the glob pattern for macro usages.
-fn get_metric_names(pattern: &str) -> Result<Vec<String>> {
-    let src_files = glob(pattern)?;
-
-    let regexes = [
-        Regex::new(GET_COUNTER_REGEX).expect("failed to compile regex"),
-        Regex::new(INCREMENT_METRIC_REGEX).expect("failed to compile regex"),
-        Regex::new(TICK_METRIC_REGEX).expect("failed to compile regex"),
-        Regex::new(SET_METRIC_REGEX).expect("failed to compile regex"),
-        Regex::new(RESET_METRIC_REGEX).expect("failed to compile regex"),
-        Regex::new(LOAD_METRIC_REGEX).expect("failed to compile regex"),
-    ];
-
-    let mut metric_names = HashSet::new();
-
-    for src_file in src_files.filter_map(|x| x.ok()) {
-        if let Ok(contents) = fs::read_to_string(src_file) {
-            for re in regexes.iter() {
-                for captures in re.captures_iter(&contents) {
-                    if let Some(name) = captures.get(1) {
-                        metric_names.insert(name.as_str().to_owned());
-                    }
-                }
+/// Metrics with labels are visited once per series, passing the `(key, value)` pairs of the
+/// series as `labels`. All series of a metric are visited one after another.
+pub trait Visitor {
+    /// Visit the counter described by `info` with its current `value`.
+    fn counter(&mut self, info: &MetricInfo, labels: &[(&str, &str)], value: u64);
+
+    /// Visit the gauge described by `info` with its current `value`.
+    fn gauge(&mut self, info: &MetricInfo, labels: &[(&str, &str)], value: i64);
+
+    /// Visit the histogram described by `info` with its current state.
+    fn histogram(
+        &mut self,
+        info: &MetricInfo,
+        labels: &[(&str, &str)],
+        histogram: &HistogramSnapshot,
+    );
+}
+
+/// Lookup of metrics by name.
+///
+/// Implemented by the generated `MetricsRecorder`, for callers which only know the name of a
+/// metric at runtime, like the [`facade`](crate::facade) bridge.
+pub trait MetricLookup {
+    /// Borrow the value backing the metric `name`, `None` if there is no such metric.
+    fn get_by_name(&self, name: &str) -> Option<MetricRef<'_>>;
+}
+
+/// Borrow of the value backing a metric, returned by the generated `MetricsRecorder::get`.
+#[derive(Clone, Copy)]
+pub enum MetricRef<'a> {
+    Counter(&'a AtomicU64),
+    /// Counter backed by another atomic type, see [`MetricsBuilder::atomic_type`].
+    CustomCounter(&'a dyn AnyCounter),
+    /// Counter declared as hot, see [`ShardedCounter`].
+    HotCounter(&'a ShardedCounter),
+    Gauge(&'a AtomicI64),
+    Histogram(&'a dyn AnyHistogram),
+    LabeledCounter(&'a dyn AnyLabeledCounter),
+    CounterFamily(&'a CounterFamily),
+}
+
+impl<'a> MetricRef<'a> {
+    /// Get the atomic value of a counter, `None` for hot counters and other kinds of metrics.
+    pub fn as_counter(self) -> Option<&'a AtomicU64> {
+        match self {
+            MetricRef::Counter(counter) => Some(counter),
+            _ => None,
+        }
+    }
+
+    /// Load the value of a plain or hot counter, `None` for other kinds of metrics.
+    pub fn load_counter(self) -> Option<u64> {
+        match self {
+            MetricRef::Counter(counter) => Some(counter.load(Ordering::Relaxed)),
+            MetricRef::HotCounter(counter) => Some(counter.load(Ordering::Relaxed)),
+            MetricRef::CustomCounter(counter) => Some(counter.value()),
+            _ => None,
+        }
+    }
+}
+
+/// Object-safe access to a counter regardless of its atomic type.
+pub trait AnyCounter: Sync {
+    /// Load the value of the counter.
+    fn value(&self) -> u64;
+
+    /// Increment the counter by `value`, wrapping around at the bounds of the atomic type.
+    fn add(&self, value: u64);
+}
+
+impl AnyCounter for AtomicU64 {
+    fn value(&self) -> u64 {
+        self.load(Ordering::Relaxed)
+    }
+
+    fn add(&self, value: u64) {
+        self.fetch_add(value, Ordering::Relaxed);
+    }
+}
+
+impl AnyCounter for AtomicU32 {
+    fn value(&self) -> u64 {
+        self.load(Ordering::Relaxed).into()
+    }
+
+    fn add(&self, value: u64) {
+        self.fetch_add(value as u32, Ordering::Relaxed);
+    }
+}
+
+impl AnyCounter for AtomicUsize {
+    fn value(&self) -> u64 {
+        self.load(Ordering::Relaxed) as u64
+    }
+
+    fn add(&self, value: u64) {
+        self.fetch_add(value as usize, Ordering::Relaxed);
+    }
+}
+
+/// Static description of a metric, available at runtime as `MetricsRecorder::METADATA`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MetricInfo {
+    pub name: &'static str,
+    pub kind: MetricKind,
+    /// Description of what is measured.
+    pub help: Option<&'static str>,
+    /// Unit of the measured values, e.g. `seconds` or `bytes`.
+    pub unit: Option<&'static str>,
+    /// Whether the value only decreases by resets, i.e. for histograms and for counters which are
+    /// not only updated with `set_metric!`. Exporters expose other counters as gauges.
+    pub monotonic: bool,
+}
+
+impl MetricInfo {
+    /// Kind of metric exporters expose the metric as, a gauge for counters which are not
+    /// monotonic.
+    pub fn exported_kind(&self) -> MetricKind {
+        match self.kind {
+            MetricKind::Counter if !self.monotonic => MetricKind::Gauge,
+            kind => kind,
+        }
+    }
+}
+
+/// Append formatted text to a `String`, like `write!` without the result, which is always `Ok` for
+/// a `String`.
+macro_rules! push_fmt {
+    ($out:expr, $($arg:tt)*) => {{
+        use std::fmt::Write as _;
+        let _ = $out.write_fmt(format_args!($($arg)*));
+    }};
+}
+pub(crate) use push_fmt;
+
+// The locks of this crate only guard collections which are never left in an inconsistent state,
+// so a lock poisoned by a panicking thread is used as is.
+
+pub(crate) fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|err| err.into_inner())
+}
+
+pub(crate) fn read<T>(lock: &RwLock<T>) -> RwLockReadGuard<'_, T> {
+    lock.read().unwrap_or_else(|err| err.into_inner())
+}
+
+pub(crate) fn write<T>(lock: &RwLock<T>) -> RwLockWriteGuard<'_, T> {
+    lock.write().unwrap_or_else(|err| err.into_inner())
+}
+
+/// Metrics collected by the tests of the exporters.
+#[cfg(test)]
+pub(crate) mod fixtures {
+    use crate::{Collect, HistogramSnapshot, MetricInfo, MetricKind, Visitor};
+    use std::sync::atomic::{AtomicU64, Ordering};
+
+    /// Description of the metric `name` without help and unit, monotonic unless it is a gauge.
+    pub(crate) const fn info(name: &'static str, kind: MetricKind) -> MetricInfo {
+        MetricInfo {
+            name,
+            kind,
+            help: None,
+            unit: None,
+            monotonic: !matches!(kind, MetricKind::Gauge),
+        }
+    }
+
+    /// One metric of every kind: the counter `requests` with a label and help, the counter
+    /// `queued` which is only set, the gauge `in_flight` and the histogram `latency`.
+    pub(crate) struct Metrics {
+        /// Value of `requests`, all other values are fixed.
+        pub(crate) requests: AtomicU64,
+    }
+
+    impl Metrics {
+        pub(crate) const fn new(requests: u64) -> Self {
+            Self {
+                requests: AtomicU64::new(requests),
             }
         }
     }
 
-    let mut metric_names: Vec<_> = metric_names.into_iter().collect();
-    metric_names.sort();
+    impl Collect for Metrics {
+        fn collect(&self, visitor: &mut dyn Visitor) {
+            visitor.counter(
+                &MetricInfo {
+                    help: Some("Handled requests."),
+                    ..info("requests", MetricKind::Counter)
+                },
+                &[("method", "get")],
+                self.requests.load(Ordering::Relaxed),
+            );
+            visitor.counter(
+                &MetricInfo {
+                    monotonic: false,
+                    ..info("queued", MetricKind::Counter)
+                },
+                &[],
+                1,
+            );
+            visitor.gauge(&info("in_flight", MetricKind::Gauge), &[], -2);
+            visitor.histogram(
+                &info("latency", MetricKind::Histogram),
+                &[],
+                &HistogramSnapshot {
+                    bounds: &[10],
+                    buckets: vec![1, 2],
+                    sum: 40,
+                    count: 3,
+                },
+            );
+        }
+    }
+
+    /// Single series of a counter, e.g. to test how names and labels are escaped.
+    pub(crate) struct Series {
+        pub(crate) info: MetricInfo,
+        pub(crate) labels: &'static [(&'static str, &'static str)],
+        pub(crate) value: u64,
+    }
 
-    Ok(metric_names)
+    impl Collect for Series {
+        fn collect(&self, visitor: &mut dyn Visitor) {
+            visitor.counter(&self.info, self.labels, self.value);
+        }
+    }
 }
@@ -0,0 +1,374 @@
+//! Macros accessing the metrics of the generated `METRICS_RECORDER`.
+//!
+//! Every macro also has a form taking the path of another recorder after `in`, e.g.
+//! `tick_metric!(in crate::metrics::RECORDER, name)`, so one binary can keep several recorders.
+//!
+//! Building with `--cfg atomic_metrics_disabled` compiles all metrics out. The macros then only
+//! type-check their arguments without evaluating them, and loads return zero.
+//!
+//! The counter macros accept the labels of a labeled counter or a counter family as trailing
+//! arguments, after the metric name and the value, e.g.
+//! `increment_metric!(http_requests, 3, Method::Get, Status::Ok)`.
+
+/// Expand to the first block if metrics are enabled and to the second one otherwise.
+#[cfg(not(atomic_metrics_disabled))]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __metrics_enabled_or {
+    ({ $($enabled:tt)* } else { $($disabled:tt)* }) => {
+        $($enabled)*
+    };
+}
+
+/// Expand to the first block if metrics are enabled and to the second one otherwise.
+#[cfg(atomic_metrics_disabled)]
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __metrics_enabled_or {
+    ({ $($enabled:tt)* } else { $($disabled:tt)* }) => {
+        $($disabled)*
+    };
+}
+
+/// Counter borrowed by `get_counter!` when metrics are compiled out.
+#[cfg(atomic_metrics_disabled)]
+#[doc(hidden)]
+pub static __DISABLED_COUNTER: std::sync::atomic::AtomicU64 = std::sync::atomic::AtomicU64::new(0);
+
+/// Get the counter `name`, or its series with the given labels, as borrow of the atomic value.
+#[macro_export]
+macro_rules! get_counter {
+    (in $recorder:path, $name:ident) => {
+        $crate::__metrics_enabled_or!({
+            &$recorder.$name
+        } else {
+            &$crate::__DISABLED_COUNTER
+        })
+    };
+    (in $recorder:path, $name:ident, $($label:expr),+) => {
+        $crate::__metrics_enabled_or!({
+            $recorder.$name.with(($($label,)+))
+        } else {
+            {
+                let _ = || {
+                    $(let _ = &$label;)+
+                };
+                &$crate::__DISABLED_COUNTER
+            }
+        })
+    };
+    ($name:ident $(, $label:expr)*) => {
+        $crate::get_counter!(in METRICS_RECORDER, $name $(, $label)*)
+    };
+}
+
+/// Increment the counter `name`, or its series with the given labels, by `value`.
+#[macro_export]
+macro_rules! increment_metric {
+    (in $recorder:path, $name:ident, $value:expr) => {
+        $crate::__metrics_enabled_or!({
+            $recorder
+                .$name
+                .fetch_add($value, std::sync::atomic::Ordering::Relaxed)
+        } else {
+            {
+                let _ = || {
+                    let _ = $value;
+                };
+                0u64
+            }
+        })
+    };
+    (in $recorder:path, $name:ident, $value:expr, $($label:expr),+) => {
+        $crate::__metrics_enabled_or!({
+            $recorder
+                .$name
+                .with(($($label,)+))
+                .fetch_add($value, std::sync::atomic::Ordering::Relaxed)
+        } else {
+            {
+                let _ = || {
+                    let _ = $value;
+                };
+                let _ = || {
+                    $(let _ = &$label;)+
+                };
+                0u64
+            }
+        })
+    };
+    ($name:ident, $value:expr $(, $label:expr)*) => {
+        $crate::increment_metric!(in METRICS_RECORDER, $name, $value $(, $label)*)
+    };
+}
+
+/// Increment the counter `name`, or its series with the given labels, by one.
+#[macro_export]
+macro_rules! tick_metric {
+    (in $recorder:path, $name:ident) => {
+        $crate::__metrics_enabled_or!({
+            $recorder
+                .$name
+                .fetch_add(1, std::sync::atomic::Ordering::Relaxed)
+        } else {
+            0u64
+        })
+    };
+    (in $recorder:path, $name:ident, $($label:expr),+) => {
+        $crate::increment_metric!(in $recorder, $name, 1, $($label),+)
+    };
+    ($name:ident $(, $label:expr)*) => {
+        $crate::tick_metric!(in METRICS_RECORDER, $name $(, $label)*)
+    };
+}
+
+/// Increment the counter `name` by one and keep the trace id `trace_id` as its latest exemplar.
+///
+/// Exemplars are exposed by the [OpenMetrics](crate::openmetrics) exporter. Only counters without
+/// labels used with this macro keep exemplars, see also
+/// [`Options::exemplars`](crate::Options::exemplars).
+#[macro_export]
+macro_rules! tick_metric_with_exemplar {
+    (in $recorder:path, $name:ident, $trace_id:expr) => {
+        $crate::__metrics_enabled_or!({
+            {
+                let previous = $recorder
+                    .$name
+                    .fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+                $recorder.__exemplars.$name.record($trace_id, 1);
+                previous
+            }
+        } else {
+            {
+                let _ = || {
+                    let _ = &$trace_id;
+                };
+                0u64
+            }
+        })
+    };
+    ($name:ident, $trace_id:expr) => {
+        $crate::tick_metric_with_exemplar!(in METRICS_RECORDER, $name, $trace_id)
+    };
+}
+
+/// Set the counter `name`, or its series with the given labels, to `value`.
+#[macro_export]
+macro_rules! set_metric {
+    (in $recorder:path, $name:ident, $value:expr) => {
+        $crate::__metrics_enabled_or!({
+            $recorder
+                .$name
+                .store($value, std::sync::atomic::Ordering::Relaxed)
+        } else {
+            {
+                let _ = || {
+                    let _ = $value;
+                };
+            }
+        })
+    };
+    (in $recorder:path, $name:ident, $value:expr, $($label:expr),+) => {
+        $crate::__metrics_enabled_or!({
+            $recorder
+                .$name
+                .with(($($label,)+))
+                .store($value, std::sync::atomic::Ordering::Relaxed)
+        } else {
+            {
+                let _ = || {
+                    let _ = $value;
+                };
+                let _ = || {
+                    $(let _ = &$label;)+
+                };
+            }
+        })
+    };
+    ($name:ident, $value:expr $(, $label:expr)*) => {
+        $crate::set_metric!(in METRICS_RECORDER, $name, $value $(, $label)*)
+    };
+}
+
+/// Reset the counter `name`, or its series with the given labels, to zero.
+#[macro_export]
+macro_rules! reset_metric {
+    (in $recorder:path, $name:ident) => {
+        $crate::__metrics_enabled_or!({
+            $recorder
+                .$name
+                .store(0, std::sync::atomic::Ordering::Relaxed)
+        } else {
+            ()
+        })
+    };
+    (in $recorder:path, $name:ident, $($label:expr),+) => {
+        $crate::set_metric!(in $recorder, $name, 0, $($label),+)
+    };
+    ($name:ident $(, $label:expr)*) => {
+        $crate::reset_metric!(in METRICS_RECORDER, $name $(, $label)*)
+    };
+}
+
+/// Load the value of the counter `name`, or of its series with the given labels.
+#[macro_export]
+macro_rules! load_metric {
+    (in $recorder:path, $name:ident) => {
+        $crate::__metrics_enabled_or!({
+            $recorder
+                .$name
+                .load(std::sync::atomic::Ordering::Relaxed)
+        } else {
+            0u64
+        })
+    };
+    (in $recorder:path, $name:ident, $($label:expr),+) => {
+        $crate::__metrics_enabled_or!({
+            $recorder
+                .$name
+                .with(($($label,)+))
+                .load(std::sync::atomic::Ordering::Relaxed)
+        } else {
+            {
+                let _ = || {
+                    $(let _ = &$label;)+
+                };
+                0u64
+            }
+        })
+    };
+    ($name:ident $(, $label:expr)*) => {
+        $crate::load_metric!(in METRICS_RECORDER, $name $(, $label)*)
+    };
+}
+
+/// Increment the gauge `name` by one or by `value`.
+#[macro_export]
+macro_rules! inc_gauge {
+    (in $recorder:path, $name:ident) => {
+        $crate::inc_gauge!(in $recorder, $name, 1)
+    };
+    (in $recorder:path, $name:ident, $value:expr) => {
+        $crate::__metrics_enabled_or!({
+            $recorder
+                .$name
+                .fetch_add($value, std::sync::atomic::Ordering::Relaxed)
+        } else {
+            {
+                let _ = || {
+                    let _ = $value;
+                };
+                0i64
+            }
+        })
+    };
+    ($name:ident $(, $value:expr)?) => {
+        $crate::inc_gauge!(in METRICS_RECORDER, $name $(, $value)?)
+    };
+}
+
+/// Decrement the gauge `name` by one or by `value`.
+#[macro_export]
+macro_rules! dec_gauge {
+    (in $recorder:path, $name:ident) => {
+        $crate::dec_gauge!(in $recorder, $name, 1)
+    };
+    (in $recorder:path, $name:ident, $value:expr) => {
+        $crate::__metrics_enabled_or!({
+            $recorder
+                .$name
+                .fetch_sub($value, std::sync::atomic::Ordering::Relaxed)
+        } else {
+            {
+                let _ = || {
+                    let _ = $value;
+                };
+                0i64
+            }
+        })
+    };
+    ($name:ident $(, $value:expr)?) => {
+        $crate::dec_gauge!(in METRICS_RECORDER, $name $(, $value)?)
+    };
+}
+
+/// Set the gauge `name` to `value`.
+#[macro_export]
+macro_rules! set_gauge {
+    (in $recorder:path, $name:ident, $value:expr) => {
+        $crate::__metrics_enabled_or!({
+            $recorder
+                .$name
+                .store($value, std::sync::atomic::Ordering::Relaxed)
+        } else {
+            {
+                let _ = || {
+                    let _ = $value;
+                };
+            }
+        })
+    };
+    ($name:ident, $value:expr) => {
+        $crate::set_gauge!(in METRICS_RECORDER, $name, $value)
+    };
+}
+
+/// Load the value of the gauge `name`.
+#[macro_export]
+macro_rules! load_gauge {
+    (in $recorder:path, $name:ident) => {
+        $crate::__metrics_enabled_or!({
+            $recorder
+                .$name
+                .load(std::sync::atomic::Ordering::Relaxed)
+        } else {
+            0i64
+        })
+    };
+    ($name:ident) => {
+        $crate::load_gauge!(in METRICS_RECORDER, $name)
+    };
+}
+
+/// Record `value` as an observation of the histogram `name`.
+#[macro_export]
+macro_rules! observe_histogram {
+    (in $recorder:path, $name:ident, $value:expr) => {
+        $crate::__metrics_enabled_or!({
+            $recorder.$name.observe($value)
+        } else {
+            {
+                let _ = || {
+                    let _ = $value;
+                };
+            }
+        })
+    };
+    ($name:ident, $value:expr) => {
+        $crate::observe_histogram!(in METRICS_RECORDER, $name, $value)
+    };
+}
+
+/// Perform the updates in the body as a group, such that `snapshot_consistent` observes either
+/// all or none of them.
+///
+/// Groups must not be nested. The group covers the recorder given as `in path;` before the
+/// body, or `METRICS_RECORDER` otherwise.
+#[macro_export]
+macro_rules! consistent_update {
+    (in $recorder:path; $($body:tt)*) => {
+        $crate::__metrics_enabled_or!({
+            {
+                let _guard = $recorder.begin_consistent_update();
+                $($body)*
+            }
+        } else {
+            {
+                $($body)*
+            }
+        })
+    };
+    ($($body:tt)*) => {
+        $crate::consistent_update!(in METRICS_RECORDER; $($body)*)
+    };
+}
@@ -0,0 +1,360 @@
+//! Declarative `metrics.toml` manifest as source of truth for the generated `MetricsRecorder`.
+//!
+//! ```toml
+//! [metrics.requests]
+//! kind = "counter"
+//! help = "Number of handled requests."
+//! unit = "requests"
+//!
+//! [metrics.request_latency_us]
+//! kind = "histogram"
+//! help = "Latency of handled requests."
+//! unit = "microseconds"
+//! buckets = [100, 1000, 10000]
+//!
+//! [metrics.cache_lookups]
+//! kind = "counter"
+//! hot = true
+//!
+//! [labels]
+//! Method = ["Get", "Post"]
+//! StatusClass = ["Success", "ClientError", "ServerError"]
+//!
+//! [metrics.http_requests]
+//! kind = "counter"
+//! labels = ["Method", "StatusClass"]
+//!
+//! [metrics.tenant_requests]
+//! kind = "counter"
+//! dynamic_labels = ["tenant"]
+//! max_series = 100
+//! ```
+
+use crate::{
+    discover::{find_usages, usage_options, Sources},
+    push_fmt, Metric, MetricKind, MetricUsage, MetricsBuilder, Options,
+};
+use anyhow::{bail, Context, Result};
+use serde::Deserialize;
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs,
+    path::Path,
+};
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Manifest {
+    #[serde(default)]
+    metrics: BTreeMap<String, MetricDeclaration>,
+    /// Label enums with their variants.
+    #[serde(default)]
+    labels: BTreeMap<String, Vec<String>>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct MetricDeclaration {
+    kind: MetricKind,
+    help: Option<String>,
+    unit: Option<String>,
+    #[serde(default)]
+    labels: Vec<String>,
+    /// Names of labels whose values are only known at runtime.
+    #[serde(default)]
+    dynamic_labels: Vec<String>,
+    /// Maximum number of series of a counter with dynamic labels.
+    max_series: Option<usize>,
+    buckets: Option<Vec<u64>>,
+    #[serde(default)]
+    hot: bool,
+}
+
+/// Generate the global `MetricsRecorder` from the metrics declared in the manifest at `path`.
+///
+/// The metric usages in the source directory are checked against the manifest. A usage with a
+/// different kind than declared always fails. In `strict` mode, using an undeclared metric or
+/// declaring a metric which is never used fails as well, otherwise these only cause warnings and
+/// undeclared metrics are added to the `MetricsRecorder`.
+///
+/// Use [`MetricsBuilder::manifest`] to customize the discovery or the generated code.
+pub fn generate_metrics_recorder_from_manifest(path: impl AsRef<Path>, strict: bool) -> Result<()> {
+    MetricsBuilder::new().manifest(path, strict).generate()
+}
+
+/// Read the manifest at `path`, check the usages of the recorder `static_name` in the `sources`
+/// against it and return the metrics of the recorder with the declarations added to `options`.
+///
+/// See [`MetricsBuilder::implicit_recorder`] for `implicit`.
+pub(crate) fn load_manifest(
+    path: &Path,
+    strict: bool,
+    sources: &Sources,
+    static_name: &str,
+    implicit: bool,
+    mut options: Options,
+) -> Result<(Vec<Metric>, Options)> {
+    println!("cargo:rerun-if-changed={}", path.display());
+
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("failed to read manifest {}", path.display()))?;
+    let manifest: Manifest = toml::from_str(&contents)
+        .with_context(|| format!("failed to parse manifest {}", path.display()))?;
+
+    let mut metrics = Vec::new();
+
+    for (name, variants) in manifest.labels.iter() {
+        let variants: Vec<_> = variants.iter().map(String::as_str).collect();
+        options = options.label_enum(name, &variants);
+    }
+
+    for (name, declaration) in manifest.metrics {
+        if syn::parse_str::<syn::Ident>(&name).is_err() {
+            bail!("metric name `{name}` is not a valid identifier");
+        }
+        if !declaration.labels.is_empty() {
+            let labels: Vec<_> = declaration.labels.iter().map(String::as_str).collect();
+            options = options.labels(&name, &labels);
+        }
+        match (
+            declaration.dynamic_labels.is_empty(),
+            declaration.max_series,
+        ) {
+            (false, Some(max_series)) => {
+                let keys: Vec<_> = declaration
+                    .dynamic_labels
+                    .iter()
+                    .map(String::as_str)
+                    .collect();
+                options = options.dynamic_labels(&name, &keys, max_series);
+            }
+            (false, None) => bail!("metric `{name}` declares dynamic labels but no max_series"),
+            (true, Some(_)) => bail!("metric `{name}` declares max_series but no dynamic labels"),
+            (true, None) => {}
+        }
+        if let Some(buckets) = declaration.buckets {
+            if declaration.kind != MetricKind::Histogram {
+                bail!(
+                    "metric `{name}` declares buckets but is a {}",
+                    declaration.kind
+                );
+            }
+            options = options.histogram_buckets(&name, &buckets);
+        }
+
+        if declaration.hot {
+            options = options.hot(&name);
+        }
+
+        metrics.push(Metric {
+            name,
+            kind: declaration.kind,
+            help: declaration.help,
+            unit: declaration.unit,
+        });
+    }
+
+    let usages: Vec<_> = find_usages(sources)?
+        .into_iter()
+        .filter(|usage| usage.uses_recorder(static_name, implicit))
+        .collect();
+    options = usage_options(&usages, options);
+    let undeclared = check_usages(&metrics, &usages)?;
+
+    let mut problems = String::new();
+    for usage in undeclared.iter() {
+        push_fmt!(
+            problems,
+            "metric `{}` used in {}:{} is not declared in {}\n",
+            usage.name,
+            usage.file.display(),
+            usage.line,
+            path.display()
+        );
+    }
+    for metric in metrics.iter() {
+        if !usages.iter().any(|usage| usage.name == metric.name) {
+            push_fmt!(
+                problems,
+                "metric `{}` is declared in {} but never used\n",
+                metric.name,
+                path.display()
+            );
+        }
+    }
+
+    if strict && !problems.is_empty() {
+        bail!("{}", problems.trim_end());
+    }
+    for problem in problems.lines() {
+        println!("cargo:warning={problem}");
+    }
+
+    for usage in undeclared {
+        if !metrics.iter().any(|metric| metric.name == usage.name) {
+            metrics.push(Metric::new(usage.name.clone(), usage.kind));
+        }
+    }
+    metrics.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok((metrics, options))
+}
+
+/// Check that `usages` match the kinds of the declared `metrics` and return the undeclared ones.
+fn check_usages<'a>(metrics: &[Metric], usages: &'a [MetricUsage]) -> Result<Vec<&'a MetricUsage>> {
+    let declared: HashMap<_, _> = metrics
+        .iter()
+        .map(|metric| (metric.name.as_str(), metric.kind))
+        .collect();
+    let mut undeclared: Vec<&MetricUsage> = Vec::new();
+
+    for usage in usages {
+        match declared.get(usage.name.as_str()) {
+            Some(&kind) if kind != usage.kind => bail!(
+                "metric `{}` is declared as a {kind} but used as a {} in {}:{}",
+                usage.name,
+                usage.kind,
+                usage.file.display(),
+                usage.line
+            ),
+            Some(_) => {}
+            None => {
+                if let Some(other) = undeclared
+                    .iter()
+                    .find(|other| other.name == usage.name && other.kind != usage.kind)
+                {
+                    bail!(
+                        "metric `{}` is used as a {} in {}:{} and as a {} in {}:{}",
+                        usage.name,
+                        other.kind,
+                        other.file.display(),
+                        other.line,
+                        usage.kind,
+                        usage.file.display(),
+                        usage.line
+                    );
+                }
+                undeclared.push(usage);
+            }
+        }
+    }
+
+    Ok(undeclared)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, path::PathBuf};
+
+    const MANIFEST: &str = r#"
+        [metrics.requests]
+        kind = "counter"
+        help = "Handled requests."
+
+        [metrics.in_flight]
+        kind = "gauge"
+    "#;
+
+    /// Load `manifest` in a directory named after `test`, with `source` as the only source file.
+    fn load(test: &str, manifest: &str, source: &str, strict: bool) -> Result<Vec<Metric>> {
+        let root = env::temp_dir().join(format!(
+            "atomic_metrics_manifest_{test}_{}",
+            std::process::id()
+        ));
+        fs::create_dir_all(&root).unwrap();
+        let path = root.join("metrics.toml");
+        let src_file = root.join("lib.rs");
+        fs::write(&path, manifest).unwrap();
+        fs::write(&src_file, source).unwrap();
+        let sources = Sources {
+            files: [src_file].into(),
+            ..Sources::default()
+        };
+
+        let loaded = load_manifest(
+            &path,
+            strict,
+            &sources,
+            "METRICS_RECORDER",
+            true,
+            Options::new(),
+        );
+        fs::remove_dir_all(&root).unwrap();
+        Ok(loaded?.0)
+    }
+
+    fn names_and_kinds(metrics: &[Metric]) -> Vec<(&str, MetricKind)> {
+        metrics
+            .iter()
+            .map(|metric| (metric.name.as_str(), metric.kind))
+            .collect()
+    }
+
+    #[test]
+    fn adds_undeclared_metrics_unless_strict() {
+        let source =
+            "fn f() { tick_metric!(requests); inc_gauge!(in_flight); tick_metric!(errors); }";
+
+        let metrics = load("lenient", MANIFEST, source, false).unwrap();
+        assert_eq!(
+            names_and_kinds(&metrics),
+            [
+                ("errors", MetricKind::Counter),
+                ("in_flight", MetricKind::Gauge),
+                ("requests", MetricKind::Counter)
+            ]
+        );
+
+        let err = load("strict", MANIFEST, source, true).unwrap_err();
+        assert!(err.to_string().starts_with("metric `errors` used in "));
+        assert!(err.to_string().contains(":1 is not declared in "));
+    }
+
+    #[test]
+    fn fails_on_declared_but_unused_metrics_if_strict() {
+        let source = "fn f() { tick_metric!(requests); }";
+
+        assert!(load("unused_lenient", MANIFEST, source, false).is_ok());
+        let err = load("unused_strict", MANIFEST, source, true).unwrap_err();
+        assert!(err
+            .to_string()
+            .starts_with("metric `in_flight` is declared in "));
+    }
+
+    #[test]
+    fn fails_on_usages_of_another_kind_than_declared() {
+        let source = "fn f() {\n    set_gauge!(requests, 1);\n}";
+
+        for strict in [false, true] {
+            let err = load(&format!("kind_{strict}"), MANIFEST, source, strict).unwrap_err();
+            assert!(err
+                .to_string()
+                .starts_with("metric `requests` is declared as a counter but used as a gauge in "));
+            assert!(err.to_string().ends_with("lib.rs:2"));
+        }
+    }
+
+    #[test]
+    fn fails_on_undeclared_metrics_used_as_different_kinds() {
+        let usage = |kind, line| MetricUsage {
+            name: "errors".to_owned(),
+            kind,
+            file: PathBuf::from("lib.rs"),
+            line,
+            recorder: None,
+            macro_name: "",
+        };
+        let usages = [
+            usage(MetricKind::Counter, 1),
+            usage(MetricKind::Counter, 2),
+            usage(MetricKind::Histogram, 3),
+        ];
+
+        assert_eq!(check_usages(&[], &usages[..2]).unwrap().len(), 2);
+        assert_eq!(
+            check_usages(&[], &usages).unwrap_err().to_string(),
+            "metric `errors` is used as a counter in lib.rs:1 and as a histogram in lib.rs:3"
+        );
+    }
+}
@@ -0,0 +1,212 @@
+//! Rendering of metrics in the [OpenMetrics] 1.0 text format.
+//!
+//! Compared to the Prometheus text format, counters get the `_total` suffix, units are exposed in
+//! `# UNIT` lines and the latest exemplar of a counter is appended to its sample. Counters which
+//! are not monotonic are exposed as gauges, without the suffix, since OpenMetrics counters must
+//! never decrease.
+//!
+//! [OpenMetrics]: https://github.com/OpenObservability/OpenMetrics/blob/main/specification/OpenMetrics.md
+
+use crate::{
+    prometheus::{escape_label_value, format_labels, sanitize_label_name, sanitize_name},
+    push_fmt, Collect, Exemplar, HistogramSnapshot, MetricInfo, MetricKind, Visitor,
+};
+use std::{fmt, time::UNIX_EPOCH};
+
+/// Content type a scraper asks for in its `Accept` header to get OpenMetrics instead of the
+/// Prometheus text format.
+pub const CONTENT_TYPE: &str = "application/openmetrics-text; version=1.0.0; charset=utf-8";
+
+/// Render all metrics of `metrics` in the OpenMetrics text format, with the exemplars of
+/// [`Collect::exemplars`].
+pub fn render(metrics: &impl Collect) -> String {
+    let mut renderer = Renderer {
+        out: String::new(),
+        current: None,
+        exemplars: metrics.exemplars(),
+    };
+    metrics.collect(&mut renderer);
+    renderer.out.push_str("# EOF\n");
+    renderer.out
+}
+
+struct Renderer {
+    out: String,
+    /// Name of the metric whose series are being rendered.
+    current: Option<String>,
+    /// Exemplars of the counters without labels, by metric name.
+    exemplars: Vec<(&'static str, Exemplar)>,
+}
+
+impl Renderer {
+    /// Write the `# TYPE`, `# UNIT` and `# HELP` lines unless they were written for a previous
+    /// series of the same metric, and return the name of the metric family.
+    ///
+    /// OpenMetrics requires the family name to end with the unit, so the `# UNIT` line is only
+    /// written for metrics named accordingly, e.g. `request_duration_seconds` with the unit
+    /// `seconds`. For counters, a `_total` suffix is not part of the family name.
+    fn header(&mut self, info: &MetricInfo) -> String {
+        let mut name = sanitize_name(info.name);
+        if info.exported_kind() == MetricKind::Counter {
+            if let Some(stripped) = name.strip_suffix("_total") {
+                name.truncate(stripped.len());
+            }
+        }
+        let unit = info
+            .unit
+            .map(sanitize_label_name)
+            .filter(|unit| name.ends_with(&format!("_{unit}")));
+
+        if self.current.as_ref() == Some(&name) {
+            return name;
+        }
+
+        push_fmt!(self.out, "# TYPE {name} {}\n", info.exported_kind());
+        if let Some(unit) = &unit {
+            push_fmt!(self.out, "# UNIT {name} {unit}\n");
+        }
+        if let Some(help) = info.help {
+            push_fmt!(self.out, "# HELP {name} {}\n", escape_label_value(help));
+        }
+        self.current = Some(name.clone());
+        name
+    }
+
+    fn sample(&mut self, name: &str, labels: &[(&str, &str)], value: impl fmt::Display) {
+        push_fmt!(self.out, "{name}{} {value}", format_labels(labels, None));
+    }
+}
+
+impl Visitor for Renderer {
+    fn counter(&mut self, info: &MetricInfo, labels: &[(&str, &str)], value: u64) {
+        let name = self.header(info);
+        if info.exported_kind() == MetricKind::Gauge {
+            self.sample(&name, labels, value);
+            self.out.push('\n');
+            return;
+        }
+
+        self.sample(&format!("{name}_total"), labels, value);
+        let exemplar = self
+            .exemplars
+            .iter()
+            .find(|(name, _)| labels.is_empty() && *name == info.name)
+            .map(|(_, exemplar)| exemplar);
+        if let Some(exemplar) = exemplar {
+            push_fmt!(
+                self.out,
+                " # {{trace_id=\"{}\"}} {}",
+                escape_label_value(&exemplar.trace_id),
+                exemplar.value
+            );
+            if let Ok(since_epoch) = exemplar.timestamp.duration_since(UNIX_EPOCH) {
+                push_fmt!(
+                    self.out,
+                    " {}.{:03}",
+                    since_epoch.as_secs(),
+                    since_epoch.subsec_millis()
+                );
+            }
+        }
+        self.out.push('\n');
+    }
+
+    fn gauge(&mut self, info: &MetricInfo, labels: &[(&str, &str)], value: i64) {
+        let name = self.header(info);
+        self.sample(&name, labels, value);
+        self.out.push('\n');
+    }
+
+    fn histogram(
+        &mut self,
+        info: &MetricInfo,
+        labels: &[(&str, &str)],
+        histogram: &HistogramSnapshot,
+    ) {
+        let name = self.header(info);
+        for (bound, count) in histogram.cumulative() {
+            // Bucket bounds are floating point numbers in OpenMetrics.
+            let le = match bound {
+                Some(bound) => format!("{bound}.0"),
+                None => "+Inf".to_owned(),
+            };
+            push_fmt!(
+                self.out,
+                "{name}_bucket{} {count}\n",
+                format_labels(labels, Some(&le))
+            );
+        }
+        let labels = format_labels(labels, None);
+        push_fmt!(self.out, "{name}_sum{labels} {}\n", histogram.sum);
+        push_fmt!(self.out, "{name}_count{labels} {}\n", histogram.count);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const REQUESTS: MetricInfo = MetricInfo {
+        name: "requests",
+        kind: MetricKind::Counter,
+        help: Some("Handled requests."),
+        unit: None,
+        monotonic: true,
+    };
+
+    struct Metrics;
+
+    impl Collect for Metrics {
+        fn collect(&self, visitor: &mut dyn Visitor) {
+            visitor.counter(&REQUESTS, &[], 3);
+            visitor.counter(
+                &MetricInfo {
+                    name: "http_requests",
+                    ..REQUESTS
+                },
+                &[("method", "get")],
+                2,
+            );
+            visitor.counter(
+                &MetricInfo {
+                    name: "queued_total",
+                    help: None,
+                    monotonic: false,
+                    ..REQUESTS
+                },
+                &[],
+                1,
+            );
+        }
+
+        fn exemplars(&self) -> Vec<(&'static str, Exemplar)> {
+            let exemplar = Exemplar {
+                trace_id: "4bf92f35".to_owned(),
+                value: 1,
+                timestamp: UNIX_EPOCH + Duration::from_millis(1_700_000_000_250),
+            };
+            vec![
+                ("requests", exemplar.clone()),
+                ("http_requests", exemplar.clone()),
+                ("queued_total", exemplar),
+            ]
+        }
+    }
+
+    #[test]
+    fn appends_exemplars_to_monotonic_counters_without_labels() {
+        assert_eq!(
+            render(&Metrics),
+            "# TYPE requests counter\n\
+             # HELP requests Handled requests.\n\
+             requests_total 3 # {trace_id=\"4bf92f35\"} 1 1700000000.250\n\
+             # TYPE http_requests counter\n\
+             # HELP http_requests Handled requests.\n\
+             http_requests_total{method=\"get\"} 2\n\
+             # TYPE queued_total gauge\n\
+             queued_total 1\n\
+             # EOF\n"
+        );
+    }
+}
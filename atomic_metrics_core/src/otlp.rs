@@ -0,0 +1,712 @@
+//! Exporter pushing metrics to an OpenTelemetry collector with OTLP over HTTP/protobuf.
+//!
+//! Every push sends an `ExportMetricsServiceRequest` with the current values of all metrics.
+//! Counters become cumulative monotonic sums, gauges and counters which are only set become
+//! gauges, and histograms become cumulative explicit-bucket histograms. All series of a metric
+//! are data points of the same OTLP metric, with the labels as attributes.
+//!
+//! The protobuf messages are encoded by hand and sent with a minimal HTTP/1.1 client, so only
+//! plain `http://` endpoints are supported. A collector behind TLS needs a local agent.
+
+use crate::{push, Collect, HistogramSnapshot, MetricInfo, MetricKind, PushHandle, Visitor};
+use std::{
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpStream, ToSocketAddrs},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+/// Path metrics are posted to if the endpoint has none.
+pub const DEFAULT_PATH: &str = "/v1/metrics";
+
+/// Content type of the encoded requests.
+pub const CONTENT_TYPE: &str = "application/x-protobuf";
+
+/// Time to wait for the connection to and the response of the collector.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// `AGGREGATION_TEMPORALITY_CUMULATIVE` of the OTLP `AggregationTemporality` enum.
+const CUMULATIVE: u64 = 2;
+
+/// Exporter posting metrics to an OpenTelemetry collector.
+///
+/// ```ignore
+/// let exporter = atomic_metrics_core::otlp::OtlpExporter::new("http://collector:4318")?
+///     .service_name("api");
+/// let handle = exporter.spawn(&METRICS_RECORDER, Duration::from_secs(30))?;
+/// ```
+pub struct OtlpExporter {
+    /// Host and port of the collector, also sent as `Host` header.
+    authority: String,
+    path: String,
+    resource: Vec<(String, String)>,
+    /// Start of the cumulative sums, the creation of the exporter.
+    start: SystemTime,
+}
+
+impl OtlpExporter {
+    /// Create an exporter posting to the collector at `endpoint`, e.g. `http://localhost:4318`.
+    ///
+    /// Without a path in `endpoint`, metrics are posted to [`DEFAULT_PATH`].
+    pub fn new(endpoint: &str) -> io::Result<Self> {
+        let Some(rest) = endpoint.strip_prefix("http://") else {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unsupported OTLP endpoint `{endpoint}`, expected `http://host:port`"),
+            ));
+        };
+        let (authority, path) = match rest.find('/') {
+            Some(idx) if idx + 1 < rest.len() => (&rest[..idx], &rest[idx..]),
+            Some(idx) => (&rest[..idx], DEFAULT_PATH),
+            None => (rest, DEFAULT_PATH),
+        };
+        if authority.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("OTLP endpoint `{endpoint}` has no host"),
+            ));
+        }
+
+        Ok(Self {
+            authority: authority.to_owned(),
+            path: path.to_owned(),
+            resource: vec![("service.name".to_owned(), "unknown_service".to_owned())],
+            start: SystemTime::now(),
+        })
+    }
+
+    /// Set the `service.name` resource attribute, `unknown_service` by default.
+    pub fn service_name(self, name: &str) -> Self {
+        self.resource("service.name", name)
+    }
+
+    /// Attach the attribute `key` with `value` to the resource of all metrics.
+    pub fn resource(mut self, key: &str, value: &str) -> Self {
+        match self.resource.iter_mut().find(|(k, _)| k == key) {
+            Some((_, v)) => *v = value.to_owned(),
+            None => self.resource.push((key.to_owned(), value.to_owned())),
+        }
+        self
+    }
+
+    /// Encode all metrics of `metrics` at `timestamp` as `ExportMetricsServiceRequest`.
+    pub fn encode(&self, metrics: &(impl Collect + ?Sized), timestamp: SystemTime) -> Vec<u8> {
+        let mut encoder = Encoder {
+            start: unix_nanos(self.start),
+            time: unix_nanos(timestamp),
+            metrics: Vec::new(),
+            current: None,
+        };
+        metrics.collect(&mut encoder);
+        encoder.flush();
+
+        let mut resource = Vec::new();
+        for (key, value) in &self.resource {
+            key_value(&mut resource, 1, key, value);
+        }
+
+        let mut scope = Vec::new();
+        string(&mut scope, 1, env!("CARGO_PKG_NAME"));
+        string(&mut scope, 2, env!("CARGO_PKG_VERSION"));
+
+        let mut scope_metrics = Vec::new();
+        len_delimited(&mut scope_metrics, 1, &scope);
+        for metric in &encoder.metrics {
+            len_delimited(&mut scope_metrics, 2, metric);
+        }
+
+        let mut resource_metrics = Vec::new();
+        len_delimited(&mut resource_metrics, 1, &resource);
+        len_delimited(&mut resource_metrics, 2, &scope_metrics);
+
+        let mut request = Vec::new();
+        len_delimited(&mut request, 1, &resource_metrics);
+        request
+    }
+
+    /// Post the current values of `metrics` to the collector.
+    pub fn push(&self, metrics: &(impl Collect + ?Sized)) -> io::Result<()> {
+        self.post(&self.encode(metrics, SystemTime::now()))
+    }
+
+    /// Post the encoded request `body` to the collector.
+    fn post(&self, body: &[u8]) -> io::Result<()> {
+        let addr =
+            self.authority.to_socket_addrs()?.next().ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidInput, "no collector address")
+            })?;
+        let mut stream = TcpStream::connect_timeout(&addr, TIMEOUT)?;
+        stream.set_read_timeout(Some(TIMEOUT))?;
+        stream.set_write_timeout(Some(TIMEOUT))?;
+
+        write!(
+            stream,
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: {CONTENT_TYPE}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+            self.path,
+            self.authority,
+            body.len()
+        )?;
+        stream.write_all(body)?;
+        stream.flush()?;
+
+        let mut status_line = String::new();
+        BufReader::new(stream).read_line(&mut status_line)?;
+        let status = status_line.split_whitespace().nth(1).unwrap_or_default();
+        if !status.starts_with('2') {
+            return Err(io::Error::other(format!(
+                "OTLP collector responded with `{}`",
+                status_line.trim_end()
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Post the values of `metrics` from a background thread every `interval`.
+    ///
+    /// The thread runs until the returned [`PushHandle`] is shut down or dropped, posting one last
+    /// time before it exits. Failed requests are not retried, the next one carries the current
+    /// values.
+    pub fn spawn<M>(self, metrics: &'static M, interval: Duration) -> io::Result<PushHandle>
+    where
+        M: Collect + Sync,
+    {
+        push::spawn("metrics-otlp", interval, move || self.push(metrics))
+    }
+}
+
+/// Visitor encoding the `Metric` messages of one request.
+struct Encoder {
+    start: u64,
+    time: u64,
+    /// Encoded `Metric` messages.
+    metrics: Vec<Vec<u8>>,
+    /// Metric whose series are being visited, with its encoded data points.
+    current: Option<(MetricInfo, Vec<Vec<u8>>)>,
+}
+
+impl Encoder {
+    /// Add the encoded data `point` of a series of the metric `info`.
+    fn point(&mut self, info: &MetricInfo, point: Vec<u8>) {
+        if self
+            .current
+            .as_ref()
+            .is_some_and(|(current, _)| current.name != info.name)
+        {
+            self.flush();
+        }
+        self.current
+            .get_or_insert_with(|| (*info, Vec::new()))
+            .1
+            .push(point);
+    }
+
+    /// Encode the metric whose series were visited last.
+    fn flush(&mut self) {
+        let Some((info, points)) = self.current.take() else {
+            return;
+        };
+
+        let mut data = Vec::new();
+        for point in &points {
+            len_delimited(&mut data, 1, point);
+        }
+        let data_field = match info.exported_kind() {
+            MetricKind::Counter => {
+                varint_field(&mut data, 2, CUMULATIVE);
+                varint_field(&mut data, 3, 1);
+                7
+            }
+            MetricKind::Gauge => 5,
+            MetricKind::Histogram => {
+                varint_field(&mut data, 2, CUMULATIVE);
+                9
+            }
+        };
+
+        let mut metric = Vec::new();
+        string(&mut metric, 1, info.name);
+        string(&mut metric, 2, info.help.unwrap_or_default());
+        string(&mut metric, 3, info.unit.unwrap_or_default());
+        len_delimited(&mut metric, data_field, &data);
+        self.metrics.push(metric);
+    }
+
+    /// Encode a `NumberDataPoint` with an integer value.
+    fn number(&self, labels: &[(&str, &str)], value: i64, cumulative: bool) -> Vec<u8> {
+        let mut point = Vec::new();
+        if cumulative {
+            fixed64(&mut point, 2, self.start);
+        }
+        fixed64(&mut point, 3, self.time);
+        fixed64(&mut point, 6, value as u64);
+        for (key, value) in labels {
+            key_value(&mut point, 7, key, value);
+        }
+        point
+    }
+}
+
+impl Visitor for Encoder {
+    fn counter(&mut self, info: &MetricInfo, labels: &[(&str, &str)], value: u64) {
+        let value = value.try_into().unwrap_or(i64::MAX);
+        let point = self.number(labels, value, info.monotonic);
+        self.point(info, point);
+    }
+
+    fn gauge(&mut self, info: &MetricInfo, labels: &[(&str, &str)], value: i64) {
+        let point = self.number(labels, value, false);
+        self.point(info, point);
+    }
+
+    fn histogram(
+        &mut self,
+        info: &MetricInfo,
+        labels: &[(&str, &str)],
+        histogram: &HistogramSnapshot,
+    ) {
+        let mut point = Vec::new();
+        fixed64(&mut point, 2, self.start);
+        fixed64(&mut point, 3, self.time);
+        fixed64(&mut point, 4, histogram.count);
+        fixed64(&mut point, 5, (histogram.sum as f64).to_bits());
+
+        let mut bucket_counts = Vec::new();
+        for count in &histogram.buckets {
+            bucket_counts.extend_from_slice(&count.to_le_bytes());
+        }
+        len_delimited(&mut point, 6, &bucket_counts);
+        let mut explicit_bounds = Vec::new();
+        for bound in histogram.bounds {
+            explicit_bounds.extend_from_slice(&(*bound as f64).to_le_bytes());
+        }
+        len_delimited(&mut point, 7, &explicit_bounds);
+
+        for (key, value) in labels {
+            key_value(&mut point, 9, key, value);
+        }
+        self.point(info, point);
+    }
+}
+
+fn unix_nanos(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map_or(0, |since_epoch| since_epoch.as_nanos() as u64)
+}
+
+/// Write a base 128 varint.
+fn varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    buf.push(value as u8);
+}
+
+/// Write the key of the field `field` with the wire type `wire_type`.
+fn key(buf: &mut Vec<u8>, field: u32, wire_type: u8) {
+    varint(buf, u64::from(field) << 3 | u64::from(wire_type));
+}
+
+fn varint_field(buf: &mut Vec<u8>, field: u32, value: u64) {
+    key(buf, field, 0);
+    varint(buf, value);
+}
+
+fn fixed64(buf: &mut Vec<u8>, field: u32, value: u64) {
+    key(buf, field, 1);
+    buf.extend_from_slice(&value.to_le_bytes());
+}
+
+fn len_delimited(buf: &mut Vec<u8>, field: u32, bytes: &[u8]) {
+    key(buf, field, 2);
+    varint(buf, bytes.len() as u64);
+    buf.extend_from_slice(bytes);
+}
+
+/// Write a string field, skipping empty strings like the protobuf default value.
+fn string(buf: &mut Vec<u8>, field: u32, value: &str) {
+    if !value.is_empty() {
+        len_delimited(buf, field, value.as_bytes());
+    }
+}
+
+/// Write a `KeyValue` message with a string `AnyValue`.
+fn key_value(buf: &mut Vec<u8>, field: u32, key: &str, value: &str) {
+    let mut any_value = Vec::new();
+    len_delimited(&mut any_value, 1, value.as_bytes());
+
+    let mut key_value = Vec::new();
+    string(&mut key_value, 1, key);
+    len_delimited(&mut key_value, 2, &any_value);
+    len_delimited(buf, field, &key_value);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::Metrics;
+    use std::{io::Read, net::TcpListener, thread};
+
+    /// Value of a field of a protobuf message.
+    #[derive(Debug, Clone, Copy)]
+    enum Field<'a> {
+        Varint(u64),
+        Fixed64(u64),
+        Bytes(&'a [u8]),
+    }
+
+    impl<'a> Field<'a> {
+        fn bytes(self) -> &'a [u8] {
+            match self {
+                Field::Bytes(bytes) => bytes,
+                other => panic!("expected a length-delimited field, got {other:?}"),
+            }
+        }
+
+        fn string(self) -> String {
+            String::from_utf8(self.bytes().to_vec()).unwrap()
+        }
+
+        fn number(self) -> u64 {
+            match self {
+                Field::Varint(value) | Field::Fixed64(value) => value,
+                other => panic!("expected a numeric field, got {other:?}"),
+            }
+        }
+    }
+
+    /// Decoded protobuf message, its fields in the order they were written.
+    struct Message<'a>(Vec<(u32, Field<'a>)>);
+
+    impl<'a> Message<'a> {
+        fn decode(mut buf: &'a [u8]) -> Self {
+            fn varint(buf: &mut &[u8]) -> u64 {
+                let mut value = 0;
+                for shift in (0..64).step_by(7) {
+                    let byte = buf[0];
+                    *buf = &buf[1..];
+                    value |= u64::from(byte & 0x7f) << shift;
+                    if byte < 0x80 {
+                        break;
+                    }
+                }
+                value
+            }
+
+            let mut fields = Vec::new();
+            while !buf.is_empty() {
+                let key = varint(&mut buf);
+                let field = match key & 0x7 {
+                    0 => Field::Varint(varint(&mut buf)),
+                    1 => {
+                        let (value, rest) = buf.split_at(8);
+                        buf = rest;
+                        Field::Fixed64(u64::from_le_bytes(value.try_into().unwrap()))
+                    }
+                    2 => {
+                        let len = varint(&mut buf) as usize;
+                        let (value, rest) = buf.split_at(len);
+                        buf = rest;
+                        Field::Bytes(value)
+                    }
+                    wire_type => panic!("unexpected wire type {wire_type}"),
+                };
+                fields.push(((key >> 3) as u32, field));
+            }
+            Message(fields)
+        }
+
+        /// All values of the field `number`.
+        fn all(&self, number: u32) -> Vec<Field<'a>> {
+            self.0
+                .iter()
+                .filter(|(field, _)| *field == number)
+                .map(|(_, value)| *value)
+                .collect()
+        }
+
+        /// The single value of the field `number`, `None` if it is not set.
+        fn get(&self, number: u32) -> Option<Field<'a>> {
+            let values = self.all(number);
+            assert!(values.len() <= 1, "field {number} is repeated");
+            values.first().copied()
+        }
+
+        /// The single embedded message in the field `number`.
+        fn message(&self, number: u32) -> Message<'a> {
+            Message::decode(self.get(number).unwrap().bytes())
+        }
+
+        /// The `KeyValue` messages with string values in the field `number`.
+        fn attributes(&self, number: u32) -> Vec<(String, String)> {
+            self.all(number)
+                .into_iter()
+                .map(|field| {
+                    let key_value = Message::decode(field.bytes());
+                    let value = key_value.message(2).get(1).unwrap().string();
+                    (key_value.get(1).unwrap().string(), value)
+                })
+                .collect()
+        }
+
+        /// The packed `fixed64` or `double` values in the field `number`.
+        fn packed(&self, number: u32) -> Vec<u64> {
+            self.get(number)
+                .unwrap()
+                .bytes()
+                .chunks(8)
+                .map(|chunk| u64::from_le_bytes(chunk.try_into().unwrap()))
+                .collect()
+        }
+    }
+
+    /// Data point of a decoded metric.
+    #[derive(Debug, PartialEq)]
+    struct Point {
+        start: Option<u64>,
+        time: u64,
+        attributes: Vec<(String, String)>,
+        value: PointValue,
+    }
+
+    #[derive(Debug, PartialEq)]
+    enum PointValue {
+        Int(i64),
+        Histogram {
+            count: u64,
+            sum: f64,
+            buckets: Vec<u64>,
+            bounds: Vec<f64>,
+        },
+    }
+
+    /// Data of a decoded metric, the temporality of sums and histograms is checked while decoding.
+    #[derive(Debug, PartialEq)]
+    enum Data {
+        Sum { monotonic: bool, points: Vec<Point> },
+        Gauge(Vec<Point>),
+        Histogram(Vec<Point>),
+    }
+
+    #[derive(Debug, PartialEq)]
+    struct DecodedMetric {
+        name: String,
+        description: Option<String>,
+        data: Data,
+    }
+
+    fn decode_number_point(point: Message) -> Point {
+        Point {
+            start: point.get(2).map(Field::number),
+            time: point.get(3).unwrap().number(),
+            attributes: point.attributes(7),
+            value: PointValue::Int(point.get(6).unwrap().number() as i64),
+        }
+    }
+
+    fn decode_histogram_point(point: Message) -> Point {
+        Point {
+            start: point.get(2).map(Field::number),
+            time: point.get(3).unwrap().number(),
+            attributes: point.attributes(9),
+            value: PointValue::Histogram {
+                count: point.get(4).unwrap().number(),
+                sum: f64::from_bits(point.get(5).unwrap().number()),
+                buckets: point.packed(6),
+                bounds: point.packed(7).into_iter().map(f64::from_bits).collect(),
+            },
+        }
+    }
+
+    /// Decode an `ExportMetricsServiceRequest` with a single resource and scope, returning the
+    /// resource attributes and the metrics.
+    fn decode_request(body: &[u8]) -> (Vec<(String, String)>, Vec<DecodedMetric>) {
+        let request = Message::decode(body);
+        let resource_metrics = request.message(1);
+        let resource = resource_metrics.message(1).attributes(1);
+        let scope_metrics = resource_metrics.message(2);
+        let scope = scope_metrics.message(1);
+        assert_eq!(scope.get(1).unwrap().string(), "atomic_metrics_core");
+        assert_eq!(scope.get(2).unwrap().string(), env!("CARGO_PKG_VERSION"));
+
+        let metrics = scope_metrics
+            .all(2)
+            .into_iter()
+            .map(|metric| {
+                let metric = Message::decode(metric.bytes());
+                assert!(metric.get(3).is_none(), "unexpected unit");
+                let points = |data: &Message, decode: fn(Message) -> Point| {
+                    data.all(1)
+                        .into_iter()
+                        .map(|point| decode(Message::decode(point.bytes())))
+                        .collect()
+                };
+
+                let data = if let Some(sum) = metric.get(7) {
+                    let sum = Message::decode(sum.bytes());
+                    assert_eq!(sum.get(2).unwrap().number(), CUMULATIVE);
+                    Data::Sum {
+                        monotonic: sum.get(3).is_some_and(|field| field.number() == 1),
+                        points: points(&sum, decode_number_point),
+                    }
+                } else if metric.get(5).is_some() {
+                    Data::Gauge(points(&metric.message(5), decode_number_point))
+                } else {
+                    let histogram = metric.message(9);
+                    assert_eq!(histogram.get(2).unwrap().number(), CUMULATIVE);
+                    Data::Histogram(points(&histogram, decode_histogram_point))
+                };
+
+                DecodedMetric {
+                    name: metric.get(1).unwrap().string(),
+                    description: metric.get(2).map(Field::string),
+                    data,
+                }
+            })
+            .collect();
+        (resource, metrics)
+    }
+
+    /// Accept one request on `collector`, answer it with `status` and return the request.
+    fn receive(collector: TcpListener, status: &'static str) -> thread::JoinHandle<Vec<u8>> {
+        thread::spawn(move || {
+            let (stream, _) = collector.accept().unwrap();
+            let mut reader = BufReader::new(stream);
+            let mut request = Vec::new();
+            let mut content_length = 0;
+            loop {
+                let mut line = String::new();
+                reader.read_line(&mut line).unwrap();
+                request.extend_from_slice(line.as_bytes());
+                if line == "\r\n" {
+                    break;
+                }
+                if let Some(length) = line.strip_prefix("Content-Length: ") {
+                    content_length = length.trim().parse().unwrap();
+                }
+            }
+            let mut body = vec![0; content_length];
+            reader.read_exact(&mut body).unwrap();
+            request.extend_from_slice(&body);
+            write!(
+                reader.get_mut(),
+                "HTTP/1.1 {status}\r\nContent-Length: 0\r\n\r\n"
+            )
+            .unwrap();
+            request
+        })
+    }
+
+    fn exporter(collector: &TcpListener) -> OtlpExporter {
+        let mut exporter =
+            OtlpExporter::new(&format!("http://{}", collector.local_addr().unwrap()))
+                .unwrap()
+                .service_name("api");
+        exporter.start = UNIX_EPOCH + Duration::from_secs(1);
+        exporter
+    }
+
+    #[test]
+    fn posts_encoded_metrics() {
+        let collector = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = collector.local_addr().unwrap();
+        let exporter = exporter(&collector);
+        let received = receive(collector, "200 OK");
+
+        let body = exporter.encode(&Metrics::new(3), UNIX_EPOCH + Duration::from_secs(2));
+        exporter.post(&body).unwrap();
+
+        let received = received.join().unwrap();
+        let head = format!(
+            "POST /v1/metrics HTTP/1.1\r\nHost: {addr}\r\nContent-Type: {CONTENT_TYPE}\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n",
+            body.len()
+        );
+        assert_eq!(&received[..head.len()], head.as_bytes());
+        assert_eq!(received.len(), head.len() + body.len());
+
+        let (start, time) = (Some(1_000_000_000), 2_000_000_000);
+        let (resource, metrics) = decode_request(&received[head.len()..]);
+        assert_eq!(resource, [("service.name".to_owned(), "api".to_owned())]);
+        assert_eq!(
+            metrics,
+            [
+                DecodedMetric {
+                    name: "requests".to_owned(),
+                    description: Some("Handled requests.".to_owned()),
+                    data: Data::Sum {
+                        monotonic: true,
+                        points: vec![Point {
+                            start,
+                            time,
+                            attributes: vec![("method".to_owned(), "get".to_owned())],
+                            value: PointValue::Int(3),
+                        }],
+                    },
+                },
+                DecodedMetric {
+                    name: "queued".to_owned(),
+                    description: None,
+                    data: Data::Gauge(vec![Point {
+                        start: None,
+                        time,
+                        attributes: Vec::new(),
+                        value: PointValue::Int(1),
+                    }]),
+                },
+                DecodedMetric {
+                    name: "in_flight".to_owned(),
+                    description: None,
+                    data: Data::Gauge(vec![Point {
+                        start: None,
+                        time,
+                        attributes: Vec::new(),
+                        value: PointValue::Int(-2),
+                    }]),
+                },
+                DecodedMetric {
+                    name: "latency".to_owned(),
+                    description: None,
+                    data: Data::Histogram(vec![Point {
+                        start,
+                        time,
+                        attributes: Vec::new(),
+                        value: PointValue::Histogram {
+                            count: 3,
+                            sum: 40.0,
+                            buckets: vec![1, 2],
+                            bounds: vec![10.0],
+                        },
+                    }]),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn fails_on_error_status() {
+        let collector = TcpListener::bind("127.0.0.1:0").unwrap();
+        let exporter = exporter(&collector);
+        let received = receive(collector, "503 Service Unavailable");
+
+        let err = exporter.push(&Metrics::new(3)).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "OTLP collector responded with `HTTP/1.1 503 Service Unavailable`"
+        );
+        received.join().unwrap();
+    }
+
+    #[test]
+    fn parses_endpoints() {
+        let exporter = OtlpExporter::new("http://collector:4318").unwrap();
+        assert_eq!(
+            (exporter.authority.as_str(), exporter.path.as_str()),
+            ("collector:4318", DEFAULT_PATH)
+        );
+        let exporter = OtlpExporter::new("http://collector:4318/otlp/v1/metrics").unwrap();
+        assert_eq!(exporter.path, "/otlp/v1/metrics");
+        assert!(OtlpExporter::new("https://collector:4318").is_err());
+        assert!(OtlpExporter::new("http:///v1/metrics").is_err());
+    }
+}
@@ -0,0 +1,213 @@
+//! Rendering of metrics in the [Prometheus text exposition format] 0.0.4.
+//!
+//! [Prometheus text exposition format]: https://prometheus.io/docs/instrumenting/exposition_formats/
+
+use crate::{push_fmt, Collect, HistogramSnapshot, MetricInfo, Visitor};
+use std::fmt;
+
+/// Content type announcing version 0.0.4 of the text format to scrapers.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4; charset=utf-8";
+
+/// Render all metrics of `metrics` in the Prometheus text exposition format.
+pub fn render(metrics: &impl Collect) -> String {
+    let mut renderer = Renderer {
+        out: String::new(),
+        current: None,
+    };
+    metrics.collect(&mut renderer);
+    renderer.out
+}
+
+struct Renderer {
+    out: String,
+    /// Name of the metric whose series are being rendered.
+    current: Option<String>,
+}
+
+impl Renderer {
+    /// Write the `# HELP` and `# TYPE` lines unless they were written for a previous series of
+    /// the same metric, and return the sanitized name. Metrics without help get no `# HELP` line.
+    fn header(&mut self, info: &MetricInfo) -> String {
+        let name = sanitize_name(info.name);
+        if self.current.as_ref() == Some(&name) {
+            return name;
+        }
+
+        if let Some(help) = info.help {
+            push_fmt!(self.out, "# HELP {name} {}\n", escape_help(help));
+        }
+        push_fmt!(self.out, "# TYPE {name} {}\n", info.exported_kind());
+        self.current = Some(name.clone());
+        name
+    }
+
+    fn metric(&mut self, info: &MetricInfo, labels: &[(&str, &str)], value: impl fmt::Display) {
+        let name = self.header(info);
+        push_fmt!(self.out, "{name}{} {value}\n", format_labels(labels, None));
+    }
+}
+
+impl Visitor for Renderer {
+    fn counter(&mut self, info: &MetricInfo, labels: &[(&str, &str)], value: u64) {
+        self.metric(info, labels, value);
+    }
+
+    fn gauge(&mut self, info: &MetricInfo, labels: &[(&str, &str)], value: i64) {
+        self.metric(info, labels, value);
+    }
+
+    fn histogram(
+        &mut self,
+        info: &MetricInfo,
+        labels: &[(&str, &str)],
+        histogram: &HistogramSnapshot,
+    ) {
+        let name = self.header(info);
+        for (bound, count) in histogram.cumulative() {
+            let le = match bound {
+                Some(bound) => bound.to_string(),
+                None => "+Inf".to_owned(),
+            };
+            push_fmt!(
+                self.out,
+                "{name}_bucket{} {count}\n",
+                format_labels(labels, Some(&le))
+            );
+        }
+        let labels = format_labels(labels, None);
+        push_fmt!(self.out, "{name}_sum{labels} {}\n", histogram.sum);
+        push_fmt!(self.out, "{name}_count{labels} {}\n", histogram.count);
+    }
+}
+
+/// Format `labels` and an optional `le` bucket label as `{key="value",...}`.
+pub(crate) fn format_labels(labels: &[(&str, &str)], le: Option<&str>) -> String {
+    if labels.is_empty() && le.is_none() {
+        return String::new();
+    }
+
+    let mut formatted = String::from("{");
+    for (key, value) in labels.iter().copied().chain(le.map(|le| ("le", le))) {
+        if formatted.len() > 1 {
+            formatted.push(',');
+        }
+        push_fmt!(
+            formatted,
+            "{}=\"{}\"",
+            sanitize_label_name(key),
+            escape_label_value(value)
+        );
+    }
+    formatted.push('}');
+    formatted
+}
+
+/// Replace all characters which are not allowed in a metric name with `_`.
+pub(crate) fn sanitize_name(name: &str) -> String {
+    name.char_indices()
+        .map(|(i, c)| match c {
+            'a'..='z' | 'A'..='Z' | '_' | ':' => c,
+            '0'..='9' if i > 0 => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+/// Replace all characters which are not allowed in a label name with `_`.
+pub(crate) fn sanitize_label_name(name: &str) -> String {
+    name.char_indices()
+        .map(|(i, c)| match c {
+            'a'..='z' | 'A'..='Z' | '_' => c,
+            '0'..='9' if i > 0 => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+/// Escape backslashes, double quotes and line feeds in a label value.
+pub(crate) fn escape_label_value(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+/// Escape backslashes and line feeds in a `# HELP` docstring.
+pub(crate) fn escape_help(help: &str) -> String {
+    let mut escaped = String::with_capacity(help.len());
+    for c in help.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::{info, Metrics, Series},
+        MetricKind,
+    };
+
+    #[test]
+    fn renders_every_kind() {
+        // `queued` is only set, so it is exposed as a gauge.
+        assert_eq!(
+            render(&Metrics::new(3)),
+            "# HELP requests Handled requests.\n\
+             # TYPE requests counter\n\
+             requests{method=\"get\"} 3\n\
+             # TYPE queued gauge\n\
+             queued 1\n\
+             # TYPE in_flight gauge\n\
+             in_flight -2\n\
+             # TYPE latency histogram\n\
+             latency_bucket{le=\"10\"} 1\n\
+             latency_bucket{le=\"+Inf\"} 3\n\
+             latency_sum 40\n\
+             latency_count 3\n"
+        );
+    }
+
+    #[test]
+    fn sanitizes_names() {
+        let series = Series {
+            info: info("5xx.responses-total", MetricKind::Counter),
+            labels: &[("status.class", "5xx"), ("1st", "a")],
+            value: 2,
+        };
+        assert_eq!(
+            render(&series),
+            "# TYPE _xx_responses_total counter\n\
+             _xx_responses_total{status_class=\"5xx\",_st=\"a\"} 2\n"
+        );
+    }
+
+    #[test]
+    fn escapes_label_values_and_help() {
+        let series = Series {
+            info: MetricInfo {
+                help: Some("Files read\nfrom C:\\data."),
+                ..info("files", MetricKind::Counter)
+            },
+            labels: &[("path", "C:\\data\\\"new\"\nfile")],
+            value: 1,
+        };
+        assert_eq!(
+            render(&series),
+            "# HELP files Files read\\nfrom C:\\\\data.\n\
+             # TYPE files counter\n\
+             files{path=\"C:\\\\data\\\\\\\"new\\\"\\nfile\"} 1\n"
+        );
+    }
+}
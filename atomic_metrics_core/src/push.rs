@@ -0,0 +1,192 @@
+//! Background threads periodically pushing metrics to a remote endpoint.
+
+use crate::Collect;
+use std::{
+    io::{self, Write},
+    net::{SocketAddr, TcpStream, ToSocketAddrs},
+    sync::mpsc::{self, RecvTimeoutError},
+    thread,
+    time::{Duration, SystemTime},
+};
+
+/// Time to wait for a connection to and writes to the push endpoint.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Text format of metrics sent by [`push_tcp`], e.g.
+/// [`influx::LineProtocol`](crate::influx::LineProtocol) or
+/// [`graphite::Plaintext`](crate::graphite::Plaintext).
+pub trait LineFormat: Send + 'static {
+    /// Render all metrics of `metrics` with `timestamp`.
+    fn render(&self, metrics: &dyn Collect, timestamp: SystemTime) -> String;
+}
+
+/// Handle to a running push thread.
+pub struct PushHandle {
+    shutdown: Option<mpsc::Sender<()>>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl PushHandle {
+    /// Push one last time and wait for the push thread to exit.
+    pub fn shutdown(mut self) {
+        self.stop();
+    }
+
+    fn stop(&mut self) {
+        // Dropping the sender wakes up the thread waiting for the next interval.
+        drop(self.shutdown.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Drop for PushHandle {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
+
+/// Call `push` on a background thread named `name` every `interval` and once more on shutdown.
+///
+/// Failed pushes are skipped, the next push sends the values accumulated in between.
+pub(crate) fn spawn(
+    name: &str,
+    interval: Duration,
+    mut push: impl FnMut() -> io::Result<()> + Send + 'static,
+) -> io::Result<PushHandle> {
+    let (shutdown, receiver) = mpsc::channel::<()>();
+
+    let thread = thread::Builder::new()
+        .name(name.into())
+        .spawn(move || loop {
+            let stop = match receiver.recv_timeout(interval) {
+                Err(RecvTimeoutError::Timeout) => false,
+                Ok(()) | Err(RecvTimeoutError::Disconnected) => true,
+            };
+            let _ = push();
+            if stop {
+                break;
+            }
+        })?;
+
+    Ok(PushHandle {
+        shutdown: Some(shutdown),
+        thread: Some(thread),
+    })
+}
+
+/// Write all metrics of `metrics` in `format` to the TCP endpoint `addr` from a background
+/// thread every `interval`.
+///
+/// The connection is kept open between pushes and reestablished after failures.
+///
+/// ```ignore
+/// let format = atomic_metrics_core::graphite::Plaintext::new().prefix("app");
+/// let handle = atomic_metrics_core::push_tcp("carbon:2003", format, &METRICS_RECORDER, interval)?;
+/// ```
+pub fn push_tcp<M>(
+    addr: impl ToSocketAddrs,
+    format: impl LineFormat,
+    metrics: &'static M,
+    interval: Duration,
+) -> io::Result<PushHandle>
+where
+    M: Collect + Sync,
+{
+    let addrs: Vec<SocketAddr> = addr.to_socket_addrs()?.collect();
+    let mut stream: Option<TcpStream> = None;
+
+    spawn("metrics-tcp", interval, move || {
+        let lines = format.render(metrics, SystemTime::now());
+        let connection = match stream.as_mut() {
+            Some(connection) => connection,
+            None => stream.insert(connect(&addrs)?),
+        };
+
+        let result = connection
+            .write_all(lines.as_bytes())
+            .and_then(|()| connection.flush());
+        if result.is_err() {
+            stream = None;
+        }
+        result
+    })
+}
+
+fn connect(addrs: &[SocketAddr]) -> io::Result<TcpStream> {
+    let mut error = io::Error::new(io::ErrorKind::InvalidInput, "no address to connect to");
+    for addr in addrs {
+        match TcpStream::connect_timeout(addr, TIMEOUT) {
+            Ok(stream) => {
+                // Without a timeout, an endpoint not reading would block the thread forever.
+                stream.set_write_timeout(Some(TIMEOUT))?;
+                return Ok(stream);
+            }
+            Err(err) => error = err,
+        }
+    }
+    Err(error)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        fixtures::{info, Series},
+        influx::LineProtocol,
+        MetricKind,
+    };
+    use std::{io::Read, net::TcpListener, time::UNIX_EPOCH};
+
+    static REQUESTS: Series = Series {
+        info: info("requests", MetricKind::Counter),
+        labels: &[],
+        value: 3,
+    };
+
+    /// Line protocol ignoring the time of the push, for predictable output.
+    struct FixedTime(LineProtocol);
+
+    impl LineFormat for FixedTime {
+        fn render(&self, metrics: &dyn Collect, _timestamp: SystemTime) -> String {
+            self.0
+                .render(metrics, UNIX_EPOCH + Duration::from_secs(1_700_000_000))
+        }
+    }
+
+    #[test]
+    fn pushes_lines_on_shutdown() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let format = FixedTime(LineProtocol::new().tag("host", "a"));
+        let handle = push_tcp(
+            listener.local_addr().unwrap(),
+            format,
+            &REQUESTS,
+            Duration::from_secs(3600),
+        )
+        .unwrap();
+        handle.shutdown();
+
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut received = String::new();
+        stream.read_to_string(&mut received).unwrap();
+        assert_eq!(received, "requests,host=a value=3i 1700000000000000000\n");
+    }
+
+    #[test]
+    fn skips_pushes_without_endpoint() {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        drop(listener);
+
+        let handle = push_tcp(
+            addr,
+            FixedTime(LineProtocol::new()),
+            &REQUESTS,
+            Duration::from_secs(3600),
+        )
+        .unwrap();
+        handle.shutdown();
+    }
+}
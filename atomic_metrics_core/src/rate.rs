@@ -0,0 +1,211 @@
+//! Differences between snapshots and per-second rates derived from them.
+
+use crate::{Collect, HistogramSnapshot, MetricInfo, Visitor};
+use std::time::Duration;
+
+/// Snapshot which can compute the increments since a previous snapshot.
+///
+/// Implemented by the generated `MetricsSnapshot`.
+pub trait Delta {
+    /// Compute the increments of all counters since `previous`.
+    ///
+    /// A counter which is lower than in `previous` is assumed to have been reset in between, so
+    /// its current value is the increment. Gauges are not cumulative and keep their current value.
+    fn delta(&self, previous: &Self) -> Self;
+}
+
+impl Delta for u64 {
+    fn delta(&self, previous: &Self) -> Self {
+        if *self >= *previous {
+            self - previous
+        } else {
+            *self
+        }
+    }
+}
+
+impl Delta for HistogramSnapshot {
+    fn delta(&self, previous: &Self) -> Self {
+        let was_reset = self.count < previous.count
+            || self.buckets.len() != previous.buckets.len()
+            || self
+                .buckets
+                .iter()
+                .zip(previous.buckets.iter())
+                .any(|(current, previous)| current < previous);
+        if was_reset {
+            return self.clone();
+        }
+
+        HistogramSnapshot {
+            bounds: self.bounds,
+            buckets: self
+                .buckets
+                .iter()
+                .zip(previous.buckets.iter())
+                .map(|(current, previous)| current - previous)
+                .collect(),
+            sum: self.sum.wrapping_sub(previous.sum),
+            count: self.count - previous.count,
+        }
+    }
+}
+
+/// Per-second rate of a counter, or of the observations of a histogram.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Rate {
+    pub info: MetricInfo,
+    /// Labels of the series, empty for metrics without labels.
+    pub labels: Vec<(String, String)>,
+    pub per_second: f64,
+}
+
+/// Tracker keeping the previous snapshot to compute rates since the last report.
+pub struct RateTracker<S> {
+    previous: S,
+}
+
+impl<S: Collect + Delta> RateTracker<S> {
+    /// Create a tracker computing the first rates relative to `initial`.
+    pub fn new(initial: S) -> Self {
+        Self { previous: initial }
+    }
+
+    /// Compute the rates of all counters and histograms between the previous snapshot and
+    /// `current`, which were taken `elapsed` apart. `current` becomes the new previous snapshot.
+    ///
+    /// Gauges have no rate and are skipped.
+    pub fn rates(&mut self, current: S, elapsed: Duration) -> Vec<Rate> {
+        let delta = current.delta(&self.previous);
+        self.previous = current;
+
+        let mut collector = RateCollector {
+            seconds: elapsed.as_secs_f64(),
+            rates: Vec::new(),
+        };
+        delta.collect(&mut collector);
+        collector.rates
+    }
+}
+
+struct RateCollector {
+    seconds: f64,
+    rates: Vec<Rate>,
+}
+
+impl RateCollector {
+    fn push(&mut self, info: &MetricInfo, labels: &[(&str, &str)], increment: u64) {
+        let per_second = if self.seconds > 0.0 {
+            increment as f64 / self.seconds
+        } else {
+            0.0
+        };
+        self.rates.push(Rate {
+            info: *info,
+            labels: labels
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.to_string()))
+                .collect(),
+            per_second,
+        });
+    }
+}
+
+impl Visitor for RateCollector {
+    fn counter(&mut self, info: &MetricInfo, labels: &[(&str, &str)], value: u64) {
+        self.push(info, labels, value);
+    }
+
+    fn gauge(&mut self, _info: &MetricInfo, _labels: &[(&str, &str)], _value: i64) {}
+
+    fn histogram(
+        &mut self,
+        info: &MetricInfo,
+        labels: &[(&str, &str)],
+        histogram: &HistogramSnapshot,
+    ) {
+        self.push(info, labels, histogram.count);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{fixtures::info, MetricKind};
+
+    /// Snapshot of a counter, a gauge and a histogram.
+    struct Snapshot {
+        requests: u64,
+        in_flight: i64,
+        latency: HistogramSnapshot,
+    }
+
+    impl Snapshot {
+        fn new(requests: u64, latency: &[u64]) -> Self {
+            Self {
+                requests,
+                in_flight: 5,
+                latency: HistogramSnapshot {
+                    bounds: &[10],
+                    buckets: latency.to_vec(),
+                    sum: 0,
+                    count: latency.iter().sum(),
+                },
+            }
+        }
+    }
+
+    impl Collect for Snapshot {
+        fn collect(&self, visitor: &mut dyn Visitor) {
+            visitor.counter(&info("requests", MetricKind::Counter), &[], self.requests);
+            visitor.gauge(&info("in_flight", MetricKind::Gauge), &[], self.in_flight);
+            visitor.histogram(&info("latency", MetricKind::Histogram), &[], &self.latency);
+        }
+    }
+
+    impl Delta for Snapshot {
+        fn delta(&self, previous: &Self) -> Self {
+            Self {
+                requests: self.requests.delta(&previous.requests),
+                in_flight: self.in_flight,
+                latency: self.latency.delta(&previous.latency),
+            }
+        }
+    }
+
+    fn per_second(rates: &[Rate]) -> Vec<(&str, f64)> {
+        rates
+            .iter()
+            .map(|rate| (rate.info.name, rate.per_second))
+            .collect()
+    }
+
+    #[test]
+    fn computes_first_rates_relative_to_the_initial_snapshot() {
+        let mut tracker = RateTracker::new(Snapshot::new(10, &[1, 1]));
+        let rates = tracker.rates(Snapshot::new(30, &[3, 5]), Duration::from_secs(2));
+        // Gauges have no rate.
+        assert_eq!(per_second(&rates), [("requests", 10.0), ("latency", 3.0)]);
+
+        let rates = tracker.rates(Snapshot::new(31, &[3, 6]), Duration::from_secs(1));
+        assert_eq!(per_second(&rates), [("requests", 1.0), ("latency", 1.0)]);
+    }
+
+    #[test]
+    fn counts_values_after_a_reset_as_increments() {
+        let mut tracker = RateTracker::new(Snapshot::new(100, &[4, 4]));
+        let rates = tracker.rates(Snapshot::new(6, &[2, 0]), Duration::from_secs(2));
+        assert_eq!(per_second(&rates), [("requests", 3.0), ("latency", 1.0)]);
+    }
+
+    #[test]
+    fn reports_zero_rates_for_a_zero_length_interval() {
+        let mut tracker = RateTracker::new(Snapshot::new(0, &[0, 0]));
+        let rates = tracker.rates(Snapshot::new(5, &[1, 0]), Duration::ZERO);
+        assert_eq!(per_second(&rates), [("requests", 0.0), ("latency", 0.0)]);
+
+        // The snapshot still becomes the previous one.
+        let rates = tracker.rates(Snapshot::new(7, &[1, 0]), Duration::from_secs(1));
+        assert_eq!(per_second(&rates), [("requests", 2.0), ("latency", 0.0)]);
+    }
+}
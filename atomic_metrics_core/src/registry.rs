@@ -0,0 +1,400 @@
+//! Registry of metrics created at runtime, for metrics which the build script cannot discover.
+
+use crate::{
+    read, write, AnyHistogram, Collect, CounterFamily, Delta, FamilySnapshot, Histogram,
+    HistogramSnapshot, MetricInfo, MetricKind, MetricLookup, MetricRef, Visitor, DEFAULT_BUCKETS,
+};
+use std::{
+    collections::BTreeMap,
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        RwLock,
+    },
+};
+
+/// Histogram registered at runtime, counting into the [`DEFAULT_BUCKETS`].
+type DynamicHistogram = Histogram<{ DEFAULT_BUCKETS.len() + 1 }>;
+
+/// Value of a metric registered at runtime.
+enum DynamicValue {
+    Counter(AtomicU64),
+    Family(CounterFamily),
+    Gauge(AtomicI64),
+    Histogram(DynamicHistogram),
+}
+
+/// Metric registered at runtime, allocated once and never freed.
+struct DynamicMetric {
+    info: MetricInfo,
+    value: DynamicValue,
+}
+
+/// Registry of named metrics created at runtime, e.g. by plugins.
+///
+/// The generated code declares the global `DYNAMIC_METRICS` registry next to `METRICS_RECORDER`.
+/// The snapshots and exporters of the `MetricsRecorder` include its metrics after the static
+/// metrics.
+///
+/// Every name is registered as one kind of metric: a counter with or without labels, a gauge or a
+/// histogram with the [`DEFAULT_BUCKETS`]. Registered metrics are never freed, so the number of
+/// distinct names should be bounded.
+pub struct Registry {
+    reserved: &'static [MetricInfo],
+    metrics: RwLock<BTreeMap<&'static str, &'static DynamicMetric>>,
+}
+
+impl Registry {
+    /// Create an empty registry refusing to register the names of the `reserved` metrics.
+    pub const fn new(reserved: &'static [MetricInfo]) -> Self {
+        Self {
+            reserved,
+            metrics: RwLock::new(BTreeMap::new()),
+        }
+    }
+
+    /// Get the counter `name`, registering it on first use.
+    ///
+    /// Returns `None` if `name` is the name of a static metric of the `MetricsRecorder`, which
+    /// cannot be registered at runtime, or of a registered metric of another kind, or if metrics
+    /// are compiled out.
+    pub fn counter(&self, name: &str) -> Option<&'static AtomicU64> {
+        match self.register(name, || DynamicValue::Counter(AtomicU64::new(0)))? {
+            DynamicValue::Counter(counter) => Some(counter),
+            _ => None,
+        }
+    }
+
+    /// Get the counter `name` with the label names `keys`, registering it as a family of at most
+    /// `max_series` series on first use.
+    ///
+    /// Returns `None` like [`Registry::counter`], and if the counter was registered with other
+    /// label names.
+    pub fn family(
+        &self,
+        name: &str,
+        keys: &[&str],
+        max_series: usize,
+    ) -> Option<&'static CounterFamily> {
+        let family = self.register(name, || {
+            let keys: Vec<&'static str> = keys.iter().map(|&key| &*Box::leak(key.into())).collect();
+            DynamicValue::Family(CounterFamily::new(keys.leak(), max_series))
+        })?;
+        match family {
+            DynamicValue::Family(family) if family.keys() == keys => Some(family),
+            _ => None,
+        }
+    }
+
+    /// Get the gauge `name`, registering it on first use.
+    ///
+    /// Returns `None` like [`Registry::counter`].
+    pub fn gauge(&self, name: &str) -> Option<&'static AtomicI64> {
+        match self.register(name, || DynamicValue::Gauge(AtomicI64::new(0)))? {
+            DynamicValue::Gauge(gauge) => Some(gauge),
+            _ => None,
+        }
+    }
+
+    /// Get the histogram `name`, registering it with the [`DEFAULT_BUCKETS`] on first use.
+    ///
+    /// Returns `None` like [`Registry::counter`].
+    pub fn histogram(&self, name: &str) -> Option<&'static dyn AnyHistogram> {
+        let histogram = self.register(name, || {
+            DynamicValue::Histogram(DynamicHistogram::new(DEFAULT_BUCKETS))
+        })?;
+        match histogram {
+            DynamicValue::Histogram(histogram) => Some(histogram),
+            _ => None,
+        }
+    }
+
+    /// Get the counter `name` if it was registered.
+    ///
+    /// Metrics of other kinds can be looked up with [`MetricLookup::get_by_name`].
+    pub fn get(&self, name: &str) -> Option<&'static AtomicU64> {
+        match read(&self.metrics).get(name).map(|metric| &metric.value) {
+            Some(DynamicValue::Counter(counter)) => Some(counter),
+            _ => None,
+        }
+    }
+
+    /// Load the values of all registered metrics, sorted by name.
+    pub fn snapshot(&self) -> RegistrySnapshot {
+        let mut snapshot = RegistrySnapshot::default();
+        for metric in read(&self.metrics).values() {
+            let info = metric.info;
+            match &metric.value {
+                DynamicValue::Counter(counter) => snapshot
+                    .counters
+                    .push((info, counter.load(Ordering::Relaxed))),
+                DynamicValue::Family(family) => snapshot.families.push((info, family.snapshot())),
+                DynamicValue::Gauge(gauge) => {
+                    snapshot.gauges.push((info, gauge.load(Ordering::Relaxed)))
+                }
+                DynamicValue::Histogram(histogram) => {
+                    snapshot.histograms.push((info, histogram.snapshot()))
+                }
+            }
+        }
+        snapshot
+    }
+
+    /// Get the value of the metric `name`, registering it with the value `init` on first use.
+    fn register(
+        &self,
+        name: &str,
+        init: impl FnOnce() -> DynamicValue,
+    ) -> Option<&'static DynamicValue> {
+        if self.reserved.iter().any(|info| info.name == name) {
+            return None;
+        }
+
+        crate::__metrics_enabled_or!({
+            Some(self.get_or_register(name, init))
+        } else {
+            {
+                let _ = init;
+                None
+            }
+        })
+    }
+
+    #[cfg_attr(atomic_metrics_disabled, allow(dead_code))]
+    fn get_or_register(
+        &self,
+        name: &str,
+        init: impl FnOnce() -> DynamicValue,
+    ) -> &'static DynamicValue {
+        if let Some(metric) = read(&self.metrics).get(name) {
+            return &metric.value;
+        }
+
+        let mut metrics = write(&self.metrics);
+        if let Some(metric) = metrics.get(name) {
+            return &metric.value;
+        }
+
+        let name: &'static str = Box::leak(name.into());
+        let value = init();
+        let kind = match value {
+            DynamicValue::Counter(_) | DynamicValue::Family(_) => MetricKind::Counter,
+            DynamicValue::Gauge(_) => MetricKind::Gauge,
+            DynamicValue::Histogram(_) => MetricKind::Histogram,
+        };
+        let metric = Box::leak(Box::new(DynamicMetric {
+            info: MetricInfo {
+                name,
+                kind,
+                help: None,
+                unit: None,
+                monotonic: kind != MetricKind::Gauge,
+            },
+            value,
+        }));
+        metrics.insert(name, metric);
+        &metric.value
+    }
+}
+
+impl MetricLookup for Registry {
+    fn get_by_name(&self, name: &str) -> Option<MetricRef<'_>> {
+        let metric: &'static DynamicMetric = read(&self.metrics).get(name)?;
+        Some(match &metric.value {
+            DynamicValue::Counter(counter) => MetricRef::Counter(counter),
+            DynamicValue::Family(family) => MetricRef::CounterFamily(family),
+            DynamicValue::Gauge(gauge) => MetricRef::Gauge(gauge),
+            DynamicValue::Histogram(histogram) => MetricRef::Histogram(histogram),
+        })
+    }
+}
+
+impl Collect for Registry {
+    fn collect(&self, visitor: &mut dyn Visitor) {
+        self.snapshot().collect(visitor);
+    }
+}
+
+/// Values of all metrics of a [`Registry`] at one point in time.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct RegistrySnapshot {
+    /// Description and value of every counter without labels, sorted by name.
+    pub counters: Vec<(MetricInfo, u64)>,
+    /// Description and series of every counter with labels, sorted by name.
+    pub families: Vec<(MetricInfo, FamilySnapshot)>,
+    /// Description and value of every gauge, sorted by name.
+    pub gauges: Vec<(MetricInfo, i64)>,
+    /// Description and state of every histogram, sorted by name.
+    pub histograms: Vec<(MetricInfo, HistogramSnapshot)>,
+}
+
+impl Collect for RegistrySnapshot {
+    fn collect(&self, visitor: &mut dyn Visitor) {
+        for (info, value) in self.counters.iter() {
+            visitor.counter(info, &[], *value);
+        }
+        for (info, family) in self.families.iter() {
+            family.visit(info, visitor);
+        }
+        for (info, value) in self.gauges.iter() {
+            visitor.gauge(info, &[], *value);
+        }
+        for (info, histogram) in self.histograms.iter() {
+            visitor.histogram(info, &[], histogram);
+        }
+    }
+}
+
+impl Delta for RegistrySnapshot {
+    fn delta(&self, previous: &Self) -> Self {
+        RegistrySnapshot {
+            counters: delta(&self.counters, &previous.counters),
+            families: delta(&self.families, &previous.families),
+            gauges: self.gauges.clone(),
+            histograms: delta(&self.histograms, &previous.histograms),
+        }
+    }
+}
+
+/// Compute the increments of the `current` values since the `previous` ones of the same name,
+/// both sorted by name. Values without a previous one are their own increment.
+fn delta<T: Delta + Clone>(
+    current: &[(MetricInfo, T)],
+    previous: &[(MetricInfo, T)],
+) -> Vec<(MetricInfo, T)> {
+    current
+        .iter()
+        .map(|(info, value)| {
+            let increment = match previous.binary_search_by(|(other, _)| other.name.cmp(info.name))
+            {
+                Ok(idx) => value.delta(&previous[idx].1),
+                Err(_) => value.clone(),
+            };
+            (*info, increment)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static RESERVED: [MetricInfo; 1] = [MetricInfo {
+        name: "requests",
+        kind: MetricKind::Counter,
+        help: None,
+        unit: None,
+        monotonic: true,
+    }];
+
+    #[test]
+    #[cfg(not(atomic_metrics_disabled))]
+    fn registers_counters_once() {
+        let registry = Registry::new(&RESERVED);
+        let loads = registry.counter("plugin_loads").unwrap();
+        loads.fetch_add(2, Ordering::Relaxed);
+        registry
+            .counter("plugin_loads")
+            .unwrap()
+            .fetch_add(1, Ordering::Relaxed);
+
+        assert!(std::ptr::eq(loads, registry.get("plugin_loads").unwrap()));
+        let snapshot = registry.snapshot();
+        assert_eq!(snapshot.counters.len(), 1);
+        assert_eq!(snapshot.counters[0].0.name, "plugin_loads");
+        assert_eq!(snapshot.counters[0].1, 3);
+    }
+
+    #[test]
+    #[cfg(not(atomic_metrics_disabled))]
+    fn registers_each_name_as_one_kind() {
+        let registry = Registry::new(&RESERVED);
+        let tenants = registry.family("tenant_hits", &["tenant"], 1).unwrap();
+        tenants.with(["acme"]).fetch_add(2, Ordering::Relaxed);
+        tenants.with(["other"]).fetch_add(1, Ordering::Relaxed);
+        registry
+            .gauge("queue_depth")
+            .unwrap()
+            .store(-4, Ordering::Relaxed);
+        registry.histogram("load_ms").unwrap().observe(7);
+
+        assert!(std::ptr::eq(
+            tenants,
+            registry.family("tenant_hits", &["tenant"], 1).unwrap()
+        ));
+        assert!(registry.family("tenant_hits", &["region"], 1).is_none());
+        assert!(registry.counter("tenant_hits").is_none());
+        assert!(registry.get("queue_depth").is_none());
+        assert!(registry.histogram("queue_depth").is_none());
+        assert!(registry.gauge("load_ms").is_none());
+        assert!(matches!(
+            registry.get_by_name("queue_depth"),
+            Some(MetricRef::Gauge(_))
+        ));
+
+        let snapshot = registry.snapshot();
+        assert!(snapshot.counters.is_empty());
+        assert_eq!(snapshot.families.len(), 1);
+        assert_eq!(snapshot.families[0].0.name, "tenant_hits");
+        assert_eq!(
+            snapshot.families[0].1.series,
+            [(vec!["acme".to_owned()], 2)]
+        );
+        assert_eq!(snapshot.families[0].1.overflow, 1);
+        assert_eq!(snapshot.gauges.len(), 1);
+        assert_eq!(snapshot.gauges[0].0.kind, MetricKind::Gauge);
+        assert_eq!(snapshot.gauges[0].1, -4);
+        assert_eq!(snapshot.histograms.len(), 1);
+        assert_eq!(snapshot.histograms[0].0.name, "load_ms");
+        assert_eq!(snapshot.histograms[0].1.bounds, DEFAULT_BUCKETS);
+        assert_eq!(snapshot.histograms[0].1.sum, 7);
+    }
+
+    #[test]
+    #[cfg(not(atomic_metrics_disabled))]
+    fn computes_increments_of_all_kinds() {
+        let registry = Registry::new(&RESERVED);
+        let loads = registry.counter("plugin_loads").unwrap();
+        let tenants = registry.family("tenant_hits", &["tenant"], 4).unwrap();
+        let depth = registry.gauge("queue_depth").unwrap();
+        let latency = registry.histogram("load_ms").unwrap();
+
+        loads.store(5, Ordering::Relaxed);
+        tenants.with(["acme"]).store(3, Ordering::Relaxed);
+        latency.observe(7);
+        let previous = registry.snapshot();
+
+        loads.store(2, Ordering::Relaxed);
+        tenants.with(["acme"]).fetch_add(1, Ordering::Relaxed);
+        depth.store(9, Ordering::Relaxed);
+        latency.observe(30);
+        let delta = registry.snapshot().delta(&previous);
+
+        // The counter was reset in between, so its current value is the increment.
+        assert_eq!(delta.counters[0].1, 2);
+        assert_eq!(delta.families[0].1.series, [(vec!["acme".to_owned()], 1)]);
+        assert_eq!(delta.gauges[0].1, 9);
+        assert_eq!(delta.histograms[0].1.count, 1);
+        assert_eq!(delta.histograms[0].1.sum, 30);
+    }
+
+    #[test]
+    fn refuses_static_names() {
+        let registry = Registry::new(&RESERVED);
+        assert!(registry.counter("requests").is_none());
+        assert!(registry.family("requests", &["method"], 4).is_none());
+        assert!(registry.gauge("requests").is_none());
+        assert!(registry.histogram("requests").is_none());
+        assert!(registry.get("requests").is_none());
+        assert_eq!(registry.snapshot(), RegistrySnapshot::default());
+    }
+
+    #[test]
+    #[cfg(atomic_metrics_disabled)]
+    fn registers_nothing_when_disabled() {
+        let registry = Registry::new(&RESERVED);
+        assert!(registry.counter("plugin_loads").is_none());
+        assert!(registry.gauge("queue_depth").is_none());
+        assert!(registry.get("plugin_loads").is_none());
+        assert_eq!(registry.snapshot(), RegistrySnapshot::default());
+    }
+}
@@ -0,0 +1,123 @@
+//! Counter split into cache-line padded shards for heavily contended hot paths.
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+/// Number of shards of a [`ShardedCounter`].
+pub const SHARDS: usize = 16;
+
+/// Wrapper aligning its value to its own cache line pair, avoiding false sharing also with CPUs
+/// prefetching adjacent lines.
+#[repr(align(128))]
+struct CachePadded<T>(T);
+
+/// Counter spreading increments over [`SHARDS`] cache-line padded atomics.
+///
+/// Every thread increments its own shard, so threads do not contend on a single cache line.
+/// Loading sums up all shards, which makes it more expensive than for a plain `AtomicU64`.
+///
+/// The methods mirror those of `AtomicU64`, so the metric macros work on both.
+pub struct ShardedCounter {
+    shards: [CachePadded<AtomicU64>; SHARDS],
+}
+
+impl ShardedCounter {
+    pub const fn new(value: u64) -> Self {
+        let mut shards = [const { CachePadded(AtomicU64::new(0)) }; SHARDS];
+        shards[0] = CachePadded(AtomicU64::new(value));
+        Self { shards }
+    }
+
+    /// Add `value` to the shard of the current thread.
+    ///
+    /// Returns the previous value of that shard only, not of the whole counter.
+    pub fn fetch_add(&self, value: u64, order: Ordering) -> u64 {
+        self.shards[shard_index()].0.fetch_add(value, order)
+    }
+
+    /// Load the sum of all shards.
+    pub fn load(&self, order: Ordering) -> u64 {
+        self.shards
+            .iter()
+            .fold(0, |sum, shard| sum.wrapping_add(shard.0.load(order)))
+    }
+
+    /// Set the counter to `value`.
+    ///
+    /// Increments happening concurrently may be lost.
+    pub fn store(&self, value: u64, order: Ordering) {
+        self.shards[0].0.store(value, order);
+        for shard in self.shards[1..].iter() {
+            shard.0.store(0, order);
+        }
+    }
+}
+
+impl Default for ShardedCounter {
+    fn default() -> Self {
+        Self::new(0)
+    }
+}
+
+/// Index of the shard used by the current thread, assigned round-robin on first use.
+fn shard_index() -> usize {
+    static NEXT_SHARD: AtomicUsize = AtomicUsize::new(0);
+
+    thread_local! {
+        static SHARD: usize = NEXT_SHARD.fetch_add(1, Ordering::Relaxed) % SHARDS;
+    }
+
+    SHARD.with(|shard| *shard)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+
+    #[test]
+    fn loads_the_sum_of_all_shards() {
+        // More threads than shards, so some of them share a shard.
+        const THREADS: u64 = SHARDS as u64 + 4;
+        const INCREMENTS: u64 = 1_000;
+
+        let counter = ShardedCounter::new(7);
+        thread::scope(|scope| {
+            for thread in 0..THREADS {
+                let counter = &counter;
+                scope.spawn(move || {
+                    for _ in 0..INCREMENTS {
+                        counter.fetch_add(thread + 1, Ordering::Relaxed);
+                    }
+                });
+            }
+        });
+
+        let expected = 7 + INCREMENTS * THREADS * (THREADS + 1) / 2;
+        assert_eq!(counter.load(Ordering::Relaxed), expected);
+        let used = counter
+            .shards
+            .iter()
+            .filter(|shard| shard.0.load(Ordering::Relaxed) > 0);
+        assert!(used.count() > 1);
+    }
+
+    #[test]
+    fn stores_the_value_over_all_shards() {
+        let counter = ShardedCounter::default();
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| counter.fetch_add(5, Ordering::Relaxed));
+            }
+        });
+        counter.fetch_add(1, Ordering::Relaxed);
+        assert_eq!(counter.load(Ordering::Relaxed), 21);
+
+        counter.store(3, Ordering::Relaxed);
+        assert_eq!(counter.load(Ordering::Relaxed), 3);
+        counter.fetch_add(2, Ordering::Relaxed);
+        assert_eq!(counter.load(Ordering::Relaxed), 5);
+
+        counter.store(0, Ordering::Relaxed);
+        assert_eq!(counter.load(Ordering::Relaxed), 0);
+    }
+}
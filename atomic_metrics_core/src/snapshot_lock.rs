@@ -0,0 +1,169 @@
+//! Lock making groups of updates appear atomic to snapshots.
+
+use std::{
+    hint,
+    sync::atomic::{AtomicU64, Ordering},
+    thread,
+};
+
+/// Number of spins before a waiting thread yields its time slice.
+const SPINS_BEFORE_YIELD: u32 = 64;
+
+/// Lock between groups of updates and readers taking a snapshot.
+///
+/// Any number of update groups may be active at the same time, as may any number of readers.
+/// A reader announces itself, which holds back new update groups, and then waits for the active
+/// ones to finish. Readers therefore never starve, while writers only wait during a snapshot.
+/// Update groups must not be nested, as a reader arriving in between would deadlock.
+pub struct SnapshotLock {
+    writers: AtomicU64,
+    readers: AtomicU64,
+}
+
+impl SnapshotLock {
+    pub const fn new() -> Self {
+        Self {
+            writers: AtomicU64::new(0),
+            readers: AtomicU64::new(0),
+        }
+    }
+
+    /// Start a group of updates which ends when the returned guard is dropped.
+    pub fn write(&self) -> WriteGuard<'_> {
+        loop {
+            self.writers.fetch_add(1, Ordering::SeqCst);
+            if self.readers.load(Ordering::SeqCst) == 0 {
+                return WriteGuard { lock: Some(self) };
+            }
+
+            // Step back and let the reader finish first.
+            self.writers.fetch_sub(1, Ordering::SeqCst);
+            wait_until(|| self.readers.load(Ordering::Relaxed) == 0);
+        }
+    }
+
+    /// Run `read` while no group of updates is active.
+    pub fn read<T>(&self, read: impl FnOnce() -> T) -> T {
+        self.readers.fetch_add(1, Ordering::SeqCst);
+        wait_until(|| self.writers.load(Ordering::SeqCst) == 0);
+
+        let value = read();
+
+        self.readers.fetch_sub(1, Ordering::Release);
+        value
+    }
+}
+
+impl Default for SnapshotLock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Guard of an active group of updates, see [`SnapshotLock::write`].
+pub struct WriteGuard<'a> {
+    lock: Option<&'a SnapshotLock>,
+}
+
+impl WriteGuard<'_> {
+    /// Guard which is not associated with any lock, for recorders without metrics.
+    pub const fn noop() -> Self {
+        Self { lock: None }
+    }
+}
+
+impl Drop for WriteGuard<'_> {
+    fn drop(&mut self) {
+        if let Some(lock) = self.lock {
+            lock.writers.fetch_sub(1, Ordering::Release);
+        }
+    }
+}
+
+fn wait_until(mut condition: impl FnMut() -> bool) {
+    let mut spins = 0;
+    while !condition() {
+        spins += 1;
+        if spins < SPINS_BEFORE_YIELD {
+            hint::spin_loop();
+        } else {
+            thread::yield_now();
+        }
+    }
+}
+
+// Without metrics, `consistent_update!` takes no lock.
+#[cfg(all(test, not(atomic_metrics_disabled)))]
+mod tests {
+    use super::*;
+    use std::sync::atomic::AtomicBool;
+
+    /// Two counters which are always updated together, like a generated `MetricsRecorder`.
+    struct Recorder {
+        lock: SnapshotLock,
+        sent: AtomicU64,
+        acked: AtomicU64,
+    }
+
+    impl Recorder {
+        fn begin_consistent_update(&self) -> WriteGuard<'_> {
+            self.lock.write()
+        }
+
+        fn snapshot_consistent(&self) -> (u64, u64) {
+            self.lock.read(|| {
+                (
+                    self.sent.load(Ordering::Relaxed),
+                    self.acked.load(Ordering::Relaxed),
+                )
+            })
+        }
+    }
+
+    #[test]
+    fn snapshots_never_see_half_of_a_consistent_update() {
+        const WRITERS: usize = 4;
+        const UPDATES: u64 = 1_000;
+
+        let recorder = Recorder {
+            lock: SnapshotLock::new(),
+            sent: AtomicU64::new(0),
+            acked: AtomicU64::new(0),
+        };
+        let done = AtomicBool::new(false);
+
+        thread::scope(|scope| {
+            let snapshotter = scope.spawn(|| {
+                let mut snapshots = 0;
+                while !done.load(Ordering::Relaxed) {
+                    let (sent, acked) = recorder.snapshot_consistent();
+                    assert_eq!(sent, acked);
+                    snapshots += 1;
+                }
+                snapshots
+            });
+
+            let writers: Vec<_> = (0..WRITERS)
+                .map(|_| {
+                    scope.spawn(|| {
+                        for _ in 0..UPDATES {
+                            crate::consistent_update!(in recorder;
+                                recorder.sent.fetch_add(1, Ordering::Relaxed);
+                                thread::yield_now();
+                                recorder.acked.fetch_add(1, Ordering::Relaxed);
+                            );
+                        }
+                    })
+                })
+                .collect();
+            for writer in writers {
+                writer.join().unwrap();
+            }
+            done.store(true, Ordering::Relaxed);
+            assert!(snapshotter.join().unwrap() > 0);
+        });
+
+        let total = WRITERS as u64 * UPDATES;
+        assert_eq!(recorder.snapshot_consistent(), (total, total));
+    }
+}
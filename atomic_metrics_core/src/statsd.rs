@@ -0,0 +1,399 @@
+//! Exporter pushing metrics as StatsD or DogStatsD lines over UDP.
+//!
+//! Counters are sent as increments since the previous push (`name:delta|c`), gauges and counters
+//! which are not monotonic as their current value (`name:value|g`). Histograms are sent as the
+//! increments of their observation count and sum, `name.count:delta|c` and `name.sum:delta|c`.
+//! Counters which did not change are skipped.
+//!
+//! In DogStatsD mode, labels and global tags are appended as `|#key:value`. Plain StatsD has no
+//! tags, so the label values are appended to the name instead, e.g. `http_requests.get.success`.
+
+use crate::{push, Collect, Delta, HistogramSnapshot, MetricInfo, MetricKind, PushHandle, Visitor};
+use std::{
+    collections::HashMap,
+    io,
+    net::{SocketAddr, ToSocketAddrs, UdpSocket},
+    time::Duration,
+};
+
+/// Default maximum size of a datagram, fitting into an Ethernet frame without fragmentation.
+pub const DEFAULT_MTU: usize = 1432;
+
+/// Exporter sending the changes of metrics to a StatsD agent.
+///
+/// ```ignore
+/// let exporter = atomic_metrics_core::statsd::StatsdExporter::new("127.0.0.1:8125")?
+///     .dogstatsd(true)
+///     .tag("service", "api");
+/// let handle = exporter.spawn(&METRICS_RECORDER, Duration::from_secs(10))?;
+/// ```
+pub struct StatsdExporter {
+    socket: UdpSocket,
+    target: SocketAddr,
+    mtu: usize,
+    prefix: Option<String>,
+    dogstatsd: bool,
+    tags: Vec<(String, String)>,
+    /// Values of the counters at the previous push, keyed by series.
+    previous: HashMap<String, u64>,
+}
+
+impl StatsdExporter {
+    /// Create an exporter sending to the agent at `target` from an ephemeral local port.
+    pub fn new(target: impl ToSocketAddrs) -> io::Result<Self> {
+        let target = target
+            .to_socket_addrs()?
+            .next()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no target address"))?;
+        let local: SocketAddr = if target.is_ipv4() {
+            ([0, 0, 0, 0], 0).into()
+        } else {
+            ([0u16; 8], 0).into()
+        };
+
+        Ok(Self {
+            socket: UdpSocket::bind(local)?,
+            target,
+            mtu: DEFAULT_MTU,
+            prefix: None,
+            dogstatsd: false,
+            tags: Vec::new(),
+            previous: HashMap::new(),
+        })
+    }
+
+    /// Pack lines into datagrams of at most `mtu` bytes instead of [`DEFAULT_MTU`].
+    ///
+    /// A single line longer than `mtu` is still sent, in a datagram of its own.
+    pub fn mtu(mut self, mtu: usize) -> Self {
+        self.mtu = mtu;
+        self
+    }
+
+    /// Prepend `prefix` and a dot to all metric names.
+    pub fn prefix(mut self, prefix: &str) -> Self {
+        self.prefix = Some(prefix.to_owned());
+        self
+    }
+
+    /// Whether to send labels and global tags in the DogStatsD format, disabled by default.
+    pub fn dogstatsd(mut self, dogstatsd: bool) -> Self {
+        self.dogstatsd = dogstatsd;
+        self
+    }
+
+    /// Attach the tag `key:value` to all lines, only sent in DogStatsD mode.
+    pub fn tag(mut self, key: &str, value: &str) -> Self {
+        self.tags.push((key.to_owned(), value.to_owned()));
+        self
+    }
+
+    /// Send the changes of `metrics` since the previous push.
+    ///
+    /// If sending a datagram fails, the changes in it and in the datagrams after it are sent
+    /// again by the next push.
+    pub fn push(&mut self, metrics: &impl Collect) -> io::Result<()> {
+        let (socket, target) = (&self.socket, self.target);
+        send(self.datagrams(metrics), &mut self.previous, |datagram| {
+            socket.send_to(datagram.as_bytes(), target).map(drop)
+        })
+    }
+
+    /// Format the changes of `metrics` into datagrams of at most `mtu` bytes.
+    fn datagrams(&self, metrics: &impl Collect) -> Vec<Datagram> {
+        let mut lines = Lines {
+            exporter: self,
+            datagrams: Vec::new(),
+            unchanged: Vec::new(),
+        };
+        metrics.collect(&mut lines);
+
+        // Counters without changes have nothing to send, they are remembered with the first
+        // datagram.
+        let mut datagrams = lines.datagrams;
+        if let Some(first) = datagrams.first_mut() {
+            first.counters.append(&mut lines.unchanged);
+        } else if !lines.unchanged.is_empty() {
+            datagrams.push(Datagram {
+                text: String::new(),
+                counters: lines.unchanged,
+            });
+        }
+
+        let mut packed: Vec<Datagram> = Vec::new();
+        for datagram in datagrams {
+            match packed.last_mut() {
+                Some(last)
+                    if last.text.is_empty()
+                        || last.text.len() + 1 + datagram.text.len() <= self.mtu =>
+                {
+                    if !last.text.is_empty() {
+                        last.text.push('\n');
+                    }
+                    last.text.push_str(&datagram.text);
+                    last.counters.extend(datagram.counters);
+                }
+                _ => packed.push(datagram),
+            }
+        }
+        packed
+    }
+
+    /// Push the changes of `metrics` from a background thread every `interval`.
+    ///
+    /// The thread runs until the returned [`PushHandle`] is shut down or dropped, pushing one last
+    /// time before it exits. The deltas of datagrams which failed to send are sent with the next
+    /// push.
+    pub fn spawn<M>(mut self, metrics: &'static M, interval: Duration) -> io::Result<PushHandle>
+    where
+        M: Collect + Sync,
+    {
+        push::spawn("metrics-statsd", interval, move || self.push(metrics))
+    }
+
+    /// Format the name and tags of a series, with `suffix` appended to the name.
+    fn series(&self, info: &MetricInfo, labels: &[(&str, &str)], suffix: &str) -> (String, String) {
+        let mut name = String::new();
+        if let Some(prefix) = &self.prefix {
+            name.push_str(&sanitize(prefix, &[':', '|', '@', '#']));
+            name.push('.');
+        }
+        name.push_str(&sanitize(info.name, &[':', '|', '@', '#']));
+        if !self.dogstatsd {
+            for (_, value) in labels {
+                name.push('.');
+                name.push_str(&sanitize(value, &[':', '|', '@', '#', '.']));
+            }
+        }
+        name.push_str(suffix);
+
+        let mut tags = String::new();
+        if self.dogstatsd {
+            for (key, value) in labels.iter().copied().chain(
+                self.tags
+                    .iter()
+                    .map(|(key, value)| (key.as_str(), value.as_str())),
+            ) {
+                tags.push(if tags.is_empty() { '#' } else { ',' });
+                tags.push_str(&sanitize(key, &[':', '|', ',', '#']));
+                tags.push(':');
+                tags.push_str(&sanitize(value, &['|', ',', '#']));
+            }
+        }
+
+        (name, tags)
+    }
+}
+
+/// Text of a datagram and the values of the counters to remember once it was sent.
+struct Datagram {
+    text: String,
+    /// Values of the counters sent in `text`, keyed by series.
+    counters: Vec<(String, u64)>,
+}
+
+/// Send `datagrams` in order with `send_to`, remembering the counters of every datagram sent.
+fn send(
+    datagrams: Vec<Datagram>,
+    previous: &mut HashMap<String, u64>,
+    mut send_to: impl FnMut(&str) -> io::Result<()>,
+) -> io::Result<()> {
+    for datagram in datagrams {
+        if !datagram.text.is_empty() {
+            send_to(&datagram.text)?;
+        }
+        previous.extend(datagram.counters);
+    }
+    Ok(())
+}
+
+/// Visitor formatting the lines of one push.
+struct Lines<'a> {
+    exporter: &'a StatsdExporter,
+    /// One datagram per line, possibly consisting of several lines which must not be split.
+    datagrams: Vec<Datagram>,
+    /// Values of the counters which did not change, keyed by series.
+    unchanged: Vec<(String, u64)>,
+}
+
+impl Lines<'_> {
+    fn counter(&mut self, info: &MetricInfo, labels: &[(&str, &str)], suffix: &str, value: u64) {
+        let (name, tags) = self.exporter.series(info, labels, suffix);
+        let key = format!("{name}|{tags}");
+        let previous = self.exporter.previous.get(&key).copied().unwrap_or(0);
+
+        let delta = value.delta(&previous);
+        if delta > 0 {
+            self.datagrams.push(Datagram {
+                text: line(&name, &delta.to_string(), "c", &tags),
+                counters: vec![(key, value)],
+            });
+        } else {
+            self.unchanged.push((key, value));
+        }
+    }
+}
+
+impl Visitor for Lines<'_> {
+    fn counter(&mut self, info: &MetricInfo, labels: &[(&str, &str)], value: u64) {
+        if info.exported_kind() == MetricKind::Gauge {
+            self.gauge(info, labels, value.try_into().unwrap_or(i64::MAX));
+        } else {
+            Lines::counter(self, info, labels, "", value);
+        }
+    }
+
+    fn gauge(&mut self, info: &MetricInfo, labels: &[(&str, &str)], value: i64) {
+        let (name, tags) = self.exporter.series(info, labels, "");
+        let mut gauge = line(&name, &value.to_string(), "g", &tags);
+        if value < 0 {
+            // A signed value is read as a change, so a negative value needs a reset to zero first.
+            gauge = format!("{}\n{gauge}", line(&name, "0", "g", &tags));
+        }
+        self.datagrams.push(Datagram {
+            text: gauge,
+            counters: Vec::new(),
+        });
+    }
+
+    fn histogram(
+        &mut self,
+        info: &MetricInfo,
+        labels: &[(&str, &str)],
+        histogram: &HistogramSnapshot,
+    ) {
+        Lines::counter(self, info, labels, ".count", histogram.count);
+        Lines::counter(self, info, labels, ".sum", histogram.sum);
+    }
+}
+
+fn line(name: &str, value: &str, kind: &str, tags: &str) -> String {
+    if tags.is_empty() {
+        format!("{name}:{value}|{kind}")
+    } else {
+        format!("{name}:{value}|{kind}|{tags}")
+    }
+}
+
+/// Replace line breaks and the `reserved` characters of the format with `_`.
+fn sanitize(s: &str, reserved: &[char]) -> String {
+    s.chars()
+        .map(|c| {
+            if c == '\n' || c == '\r' || reserved.contains(&c) {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixtures::Metrics;
+    use std::sync::atomic::Ordering;
+
+    /// Bind a fake agent and create an exporter sending to it.
+    fn agent() -> (UdpSocket, StatsdExporter) {
+        let agent = UdpSocket::bind("127.0.0.1:0").unwrap();
+        agent
+            .set_read_timeout(Some(Duration::from_secs(5)))
+            .unwrap();
+        let exporter = StatsdExporter::new(agent.local_addr().unwrap()).unwrap();
+        (agent, exporter)
+    }
+
+    fn receive(agent: &UdpSocket) -> String {
+        let mut datagram = [0; 1500];
+        let len = agent.recv(&mut datagram).unwrap();
+        String::from_utf8(datagram[..len].to_vec()).unwrap()
+    }
+
+    #[test]
+    fn sends_counter_deltas() {
+        let (agent, exporter) = agent();
+        let mut exporter = exporter.dogstatsd(true).tag("service", "api");
+        let metrics = Metrics::new(3);
+
+        exporter.push(&metrics).unwrap();
+        assert_eq!(
+            receive(&agent),
+            "requests:3|c|#method:get,service:api\n\
+             queued:1|g|#service:api\n\
+             in_flight:0|g|#service:api\n\
+             in_flight:-2|g|#service:api\n\
+             latency.count:3|c|#service:api\n\
+             latency.sum:40|c|#service:api"
+        );
+
+        metrics.requests.store(5, Ordering::Relaxed);
+        exporter.push(&metrics).unwrap();
+        assert_eq!(
+            receive(&agent),
+            "requests:2|c|#method:get,service:api\n\
+             queued:1|g|#service:api\n\
+             in_flight:0|g|#service:api\n\
+             in_flight:-2|g|#service:api"
+        );
+    }
+
+    #[test]
+    fn splits_datagrams_at_the_mtu() {
+        let (agent, exporter) = agent();
+        let mut exporter = exporter.prefix("app").mtu(40);
+
+        exporter.push(&Metrics::new(1)).unwrap();
+        assert_eq!(receive(&agent), "app.requests.get:1|c\napp.queued:1|g");
+        // The reset of the negative gauge stays in the same datagram.
+        assert_eq!(receive(&agent), "app.in_flight:0|g\napp.in_flight:-2|g");
+        assert_eq!(receive(&agent), "app.latency.count:3|c");
+        assert_eq!(receive(&agent), "app.latency.sum:40|c");
+    }
+
+    #[test]
+    fn resends_deltas_after_failed_sends() {
+        let (agent, mut exporter) = agent();
+        let metrics = Metrics::new(3);
+
+        // Sending to the broadcast address fails without `SO_BROADCAST`.
+        let target = exporter.target;
+        exporter.target = ([255, 255, 255, 255], target.port()).into();
+        assert!(exporter.push(&metrics).is_err());
+
+        exporter.target = target;
+        metrics.requests.store(4, Ordering::Relaxed);
+        exporter.push(&metrics).unwrap();
+        assert_eq!(
+            receive(&agent),
+            "requests.get:4|c\nqueued:1|g\nin_flight:0|g\nin_flight:-2|g\n\
+             latency.count:3|c\nlatency.sum:40|c"
+        );
+    }
+
+    #[test]
+    fn resends_only_deltas_of_failed_datagrams() {
+        let (agent, exporter) = agent();
+        let mut exporter = exporter.mtu(20);
+        let metrics = Metrics::new(3);
+
+        // Deliver the datagram with the request counter, then fail on the next one.
+        let mut sent = Vec::new();
+        let datagrams = exporter.datagrams(&metrics);
+        let result = send(datagrams, &mut exporter.previous, |datagram| {
+            if !sent.is_empty() {
+                return Err(io::Error::other("unreachable"));
+            }
+            sent.push(datagram.to_owned());
+            Ok(())
+        });
+        assert!(result.is_err());
+        assert_eq!(sent, ["requests.get:3|c"]);
+
+        exporter.push(&metrics).unwrap();
+        assert_eq!(receive(&agent), "queued:1|g");
+        assert_eq!(receive(&agent), "in_flight:0|g\nin_flight:-2|g");
+        assert_eq!(receive(&agent), "latency.count:3|c");
+        assert_eq!(receive(&agent), "latency.sum:40|c");
+    }
+}
@@ -0,0 +1,121 @@
+//! Resolution of the crates whose sources are scanned for metrics across a workspace.
+
+use anyhow::{bail, Context, Result};
+use glob::{glob, Pattern};
+use serde::Deserialize;
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+#[derive(Debug, Deserialize)]
+struct CargoManifest {
+    package: Option<Package>,
+    workspace: Option<Workspace>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Package {
+    name: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct Workspace {
+    #[serde(default)]
+    members: Vec<String>,
+    #[serde(default)]
+    exclude: Vec<String>,
+}
+
+/// Crate whose sources are scanned for metrics.
+#[derive(Debug, Clone)]
+pub(crate) struct SourceCrate {
+    pub(crate) name: String,
+    pub(crate) dir: PathBuf,
+}
+
+impl SourceCrate {
+    /// Crate in the directory `dir`, named after its `Cargo.toml`.
+    pub(crate) fn at(dir: &Path) -> Result<Self> {
+        let manifest = read_manifest(&dir.join("Cargo.toml"))?;
+        match manifest.package {
+            Some(package) => Ok(Self {
+                name: package.name,
+                dir: dir.to_owned(),
+            }),
+            None => bail!("{} does not contain a package", dir.display()),
+        }
+    }
+
+    /// Workspace member `name` of the workspace containing the crate being built.
+    pub(crate) fn member(name: &str) -> Result<Self> {
+        let root = workspace_root()?;
+        println!(
+            "cargo:rerun-if-changed={}",
+            root.join("Cargo.toml").display()
+        );
+
+        for dir in member_dirs(&root)? {
+            let member = Self::at(&dir)?;
+            if member.name == name {
+                return Ok(member);
+            }
+        }
+
+        bail!(
+            "`{name}` is not a member of the workspace at {}",
+            root.display()
+        )
+    }
+
+    /// Glob pattern matching the source files of the crate.
+    pub(crate) fn pattern(&self) -> String {
+        let dir = Pattern::escape(&self.dir.to_string_lossy());
+        format!("{dir}/src/**/*.rs")
+    }
+}
+
+/// Find the directory of the workspace containing the current directory.
+fn workspace_root() -> Result<PathBuf> {
+    let current = env::current_dir()?;
+    for dir in current.ancestors() {
+        let path = dir.join("Cargo.toml");
+        if path.is_file() && read_manifest(&path)?.workspace.is_some() {
+            return Ok(dir.to_owned());
+        }
+    }
+
+    bail!("{} is not part of a workspace", current.display())
+}
+
+/// Directories of all members of the workspace at `root`.
+fn member_dirs(root: &Path) -> Result<Vec<PathBuf>> {
+    let workspace = read_manifest(&root.join("Cargo.toml"))?
+        .workspace
+        .with_context(|| format!("{} is not a workspace", root.display()))?;
+    let root_pattern = Pattern::escape(&root.to_string_lossy());
+
+    let mut exclude = Vec::new();
+    for pattern in workspace.exclude.iter() {
+        exclude.push(Pattern::new(&format!("{root_pattern}/{pattern}"))?);
+    }
+
+    let mut dirs = Vec::new();
+    for pattern in workspace.members.iter() {
+        for dir in glob(&format!("{root_pattern}/{pattern}"))?.filter_map(|x| x.ok()) {
+            if dir.join("Cargo.toml").is_file()
+                && !exclude.iter().any(|pattern| pattern.matches_path(&dir))
+            {
+                dirs.push(dir);
+            }
+        }
+    }
+
+    Ok(dirs)
+}
+
+fn read_manifest(path: &Path) -> Result<CargoManifest> {
+    let contents =
+        fs::read_to_string(path).with_context(|| format!("failed to read {}", path.display()))?;
+    toml::from_str(&contents).with_context(|| format!("failed to parse {}", path.display()))
+}
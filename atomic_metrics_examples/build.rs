@@ -1,5 +1,14 @@
 use anyhow::Result;
+use atomic_metrics_core::MetricsBuilder;
 
 fn main() -> Result<()> {
-    atomic_metrics_core::generate_metrics_recorder()
+    atomic_metrics_core::generate_metrics_recorder_from_manifest("metrics.toml", true)?;
+
+    // A second recorder in the same module, only holding the metrics used with `in JOBS`.
+    MetricsBuilder::new()
+        .output("jobs.rs")
+        .type_name("JobsRecorder")
+        .static_name("JOBS")
+        .implicit_recorder(false)
+        .generate()
 }
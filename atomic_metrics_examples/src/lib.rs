@@ -1,3 +1,3 @@
 pub mod metrics;
 
-pub use metrics::METRICS_RECORDER;
+pub use metrics::{DYNAMIC_METRICS, METRICS_RECORDER};
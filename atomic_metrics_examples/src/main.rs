@@ -1,7 +1,13 @@
 use atomic_metrics_core::{
-    get_counter, increment_metric, load_metric, reset_metric, set_metric, tick_metric,
+    consistent_update, dec_gauge, facade::FacadeRecorder, get_counter, inc_gauge, increment_metric,
+    load_gauge, load_metric, observe_histogram, reset_metric, set_gauge, set_metric, tick_metric,
+    tick_metric_with_exemplar, RateTracker,
 };
-use atomic_metrics_examples::METRICS_RECORDER;
+use atomic_metrics_examples::{
+    metrics::{JobsMetricId, Method, MetricId, StatusClass},
+    DYNAMIC_METRICS, METRICS_RECORDER,
+};
+use std::{sync::atomic::Ordering, time::Duration};
 
 fn main() {
     println!("Examples of atomic metrics");
@@ -9,7 +15,8 @@ fn main() {
     let value = get_counter!(value);
     increment_metric!(value_inc, 3);
     tick_metric!(value_tick);
-    tick_metric!(value_tick);
+    tick_metric!(in atomic_metrics_examples::METRICS_RECORDER, value_tick);
+    tick_metric_with_exemplar!(value_tick, "4bf92f3577b34da6a3ce929d0e0e4736");
     set_metric!(value_set, 7);
 
     dbg!(value);
@@ -24,4 +31,76 @@ fn main() {
 
     reset_metric!(value_inc);
     dbg!(load_metric!(value_inc));
+
+    inc_gauge!(in_flight);
+    inc_gauge!(in_flight, 2);
+    dec_gauge!(in_flight);
+    set_gauge!(temperature, -4);
+    dbg!(load_gauge!(in_flight));
+    dbg!(load_gauge!(temperature));
+
+    for latency_us in [42, 420, 4_200, 42_000] {
+        observe_histogram!(request_latency_us, latency_us);
+    }
+    observe_histogram!(response_size_bytes, 512);
+
+    tick_metric!(http_requests, Method::Get, StatusClass::Success);
+    tick_metric!(http_requests, Method::Get, StatusClass::Success);
+    increment_metric!(http_requests, 3, Method::Post, StatusClass::ClientError);
+    dbg!(load_metric!(
+        http_requests,
+        Method::Get,
+        StatusClass::Success
+    ));
+
+    for tenant in ["acme", "globex", "acme", "initech"] {
+        tick_metric!(tenant_requests, tenant);
+    }
+    dbg!(load_metric!(tenant_requests, "acme"));
+
+    if let Some(plugin_loads) = DYNAMIC_METRICS.counter("plugin_loads") {
+        plugin_loads.fetch_add(1, Ordering::Relaxed);
+    }
+
+    let bridge = FacadeRecorder::new(&METRICS_RECORDER, &DYNAMIC_METRICS);
+    metrics::with_local_recorder(&bridge, || {
+        metrics::counter!("value_inc").increment(5);
+        metrics::counter!("http_requests", "method" => "post", "status_class" => "success")
+            .increment(1);
+        metrics::counter!("tenant_requests", "tenant" => "globex").increment(1);
+        metrics::counter!("library_cache_hits").increment(2);
+        metrics::gauge!("in_flight").increment(1.0);
+        metrics::histogram!("request_latency_us").record(250.0);
+    });
+    dbg!(load_metric!(value_inc));
+    dbg!(DYNAMIC_METRICS.get("library_cache_hits"));
+
+    consistent_update! {
+        tick_metric!(value_tick);
+        increment_metric!(value_inc, 2);
+    }
+    let snapshot = METRICS_RECORDER.snapshot_consistent();
+    dbg!(&snapshot);
+
+    let mut rate_tracker = RateTracker::new(snapshot);
+    tick_metric!(value_tick);
+    increment_metric!(value_inc, 10);
+    for rate in rate_tracker.rates(METRICS_RECORDER.snapshot(), Duration::from_secs(2)) {
+        println!("{}{:?}: {}/s", rate.info.name, rate.labels, rate.per_second);
+    }
+
+    tick_metric!(in atomic_metrics_examples::metrics::JOBS, finished_jobs);
+    dbg!(load_metric!(in atomic_metrics_examples::metrics::JOBS, finished_jobs));
+    for &id in JobsMetricId::ALL {
+        println!("{}", id.name());
+    }
+
+    for &id in MetricId::ALL {
+        if let Some(value) = METRICS_RECORDER.get(id).load_counter() {
+            println!("{}: {value}", id.name());
+        }
+    }
+
+    print!("{}", METRICS_RECORDER.render_prometheus());
+    print!("{}", METRICS_RECORDER.render_openmetrics());
 }
@@ -1 +1,2 @@
 include!(concat!(env!("OUT_DIR"), "/metrics.rs"));
+include!(concat!(env!("OUT_DIR"), "/jobs.rs"));